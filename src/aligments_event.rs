@@ -1,6 +1,8 @@
+use crate::split_read_event::SplitReadEvent;
+
 /// # Type of SVs in alignment event
 ///
-/// Have not beed used.
+/// Only `Del` events are merged.
 ///
 #[derive(Debug, Clone, PartialEq)]
 pub enum AlignEventType {
@@ -11,14 +13,16 @@ pub enum AlignEventType {
 #[derive(Debug, Clone)]
 /// struct for alignment event
 /// left and right reference consume will sum the length of D M = X for the left/right part of d
+///
+/// The coordinates are 0-based and 64-bit, contigs may be longer than 2^32 bp.
 pub struct AlignmentEvent {
     pub lchrom: String,
-    pub lstart: u32,
-    pub lend: u32,
+    pub lstart: i64,
+    pub lend: i64,
     pub lstrand: i32,
     pub rchrom: String,
-    pub rstart: u32,
-    pub rend: u32,
+    pub rstart: i64,
+    pub rend: i64,
     pub rstrand: i32, // true = forward
     pub events_num: i32,
     pub svtype: AlignEventType,
@@ -27,27 +31,21 @@ pub struct AlignmentEvent {
 impl AlignmentEvent {
     pub fn new(
         chrom: &str,
-        left_consume: &u32,
-        right_consume: &u32,
-        event_len: &u32,
+        left_consume: &i64,
+        right_consume: &i64,
+        event_len: &i64,
         pos: &i64,
         strand: &i32,
         sv_type: Option<AlignEventType>,
     ) -> AlignmentEvent {
-        let chrom_clean: String;
-        if chrom.starts_with(&"chr".to_string()) {
-            chrom_clean = chrom.strip_prefix(&"chr".to_string()).unwrap().to_string();
-        } else {
-            chrom_clean = chrom.to_string();
-        }
-        let pos2 = *pos as u32;
+        let pos2 = *pos;
 
         AlignmentEvent {
-            lchrom: chrom_clean.clone(),
+            lchrom: chrom.to_string(),
             lstart: pos2,
             lend: pos2 + left_consume,
             lstrand: *strand,
-            rchrom: chrom_clean.clone(),
+            rchrom: chrom.to_string(),
             rstart: pos2 + left_consume + event_len,
             rend: pos2 + left_consume + event_len + right_consume,
             rstrand: *strand,
@@ -55,4 +53,121 @@ impl AlignmentEvent {
             svtype: sv_type.unwrap(),
         }
     }
+
+    /// Insertion at the end of a segment, the left region is the segment itself.
+    pub fn from_segment_end(a: &SplitReadEvent) -> AlignmentEvent {
+        AlignmentEvent {
+            lchrom: a.chrom.clone(),
+            lstart: a.start,
+            lend: a.end,
+            lstrand: a.strand,
+            rchrom: a.chrom.clone(),
+            rstart: a.end,
+            rend: a.end,
+            rstrand: a.strand,
+            events_num: 1,
+            svtype: AlignEventType::Ins,
+        }
+    }
+}
+
+/// Join the left region of `a` and the right region of `b` into one deletion.
+fn join_del(a: &AlignmentEvent, b: &AlignmentEvent) -> AlignmentEvent {
+    AlignmentEvent {
+        lchrom: a.lchrom.clone(),
+        lstart: a.lstart,
+        lend: a.lend,
+        lstrand: a.lstrand,
+        rchrom: b.rchrom.clone(),
+        rstart: b.rstart,
+        rend: b.rend,
+        rstrand: b.rstrand,
+        events_num: 1, // hard code 1, STIX will parse it as a split-event.
+        svtype: AlignEventType::Del,
+    }
+}
+
+/// Return `true` if both are deletions and `a` ends close to where `b` starts.
+fn is_mergeable(a: &AlignmentEvent, b: &AlignmentEvent, merge_min: u32) -> bool {
+    a.lend.abs_diff(b.rstart) < merge_min as u64
+        && a.svtype == AlignEventType::Del
+        && b.svtype == AlignEventType::Del
+}
+
+/// # Merge adjacent deletions of one read
+///
+/// try to merge several times to handle more than two adjecent dels.
+/// 1. merge 5 times, each time will based on the previous one.
+/// 2. If the original vec is length as n, we iterally compare previous and next for the 2 to n-1 elements.
+/// 3. Only works in Del
+///
+/// ```text
+/// each round
+///  [ a, b, c, d, e]
+///    |--|--| -> idx =1
+///       |--|--| --> idx =2
+///          |--|--| --> idx =3
+///
+/// 5 round in total
+/// [ a, b, c, d, e] --> init
+/// [ ab, c,d,e] --> round 1
+/// [ abc, d, e] --> round 2
+/// ...
+/// round 5 ----> merged_alignment_vec and go down.
+/// ```
+pub fn merge_alignment_events(events: &[AlignmentEvent], merge_min: u32) -> Vec<AlignmentEvent> {
+    if events.len() == 2 {
+        let a = &events[0];
+        let b = &events[1];
+        // the right region of `b` is compared with the left region of `a` here.
+        if is_mergeable(b, a, merge_min) {
+            return vec![join_del(a, b)];
+        }
+        return events.to_vec();
+    }
+    if events.len() < 2 {
+        return events.to_vec();
+    }
+
+    let mut merge1: Vec<AlignmentEvent> = events.to_vec();
+    let mut merge2: Vec<AlignmentEvent> = vec![];
+    let mut iter_times = 5u32;
+    loop {
+        // a round needs three events, stop once the merged ones are fewer.
+        if merge1.len() < 3 {
+            return merge1;
+        }
+        iter_times -= 1;
+        let mut idx = 1usize;
+
+        while idx + 2 <= merge1.len() {
+            let prv = &merge1[idx - 1];
+            let target = &merge1[idx];
+            let nxt = &merge1[idx + 1];
+
+            let merge_prv = is_mergeable(prv, target, merge_min);
+            let merge_nxt = is_mergeable(target, nxt, merge_min);
+            if merge_prv || merge_nxt {
+                if merge_prv {
+                    merge2.push(join_del(prv, target));
+                }
+                if merge_nxt {
+                    merge2.push(join_del(target, nxt));
+                }
+            } else if idx == 1 {
+                merge2.push(prv.clone());
+                merge2.push(target.clone());
+                merge2.push(nxt.clone());
+            } else {
+                merge2.push(nxt.clone());
+            }
+            idx += 1;
+        }
+
+        if merge1.len() == merge2.len() || iter_times == 0 {
+            break;
+        }
+        merge1 = std::mem::take(&mut merge2);
+    }
+    merge2
 }
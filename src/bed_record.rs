@@ -0,0 +1,157 @@
+use rust_htslib::bgzf;
+use std::{
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::signal::{SignalType, SplitType};
+
+/// # One region of a BEDPE-like record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Side {
+    pub chrom: String,
+    /// 0-based, half-open
+    pub start: i64,
+    pub end: i64,
+    /// 1 = forward, -1 = reverse
+    pub strand: i32,
+}
+
+/// # Optional columns of the BED output
+///
+/// They come after the 9 BEDPE-like columns and before the verbose ones: the
+/// sample(`--sample-column`), `ref`, `alt` and `vaf`(`--ref-support`), then
+/// `normal`(`--normal-bam`). When one of them is written the output starts
+/// with a header line naming the columns, see [`BedColumns::header`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BedColumns {
+    pub sample: bool,
+    pub counts: bool,
+    pub normal: bool,
+}
+
+impl BedColumns {
+    /// Start of the header line, the names of the 9 BEDPE-like columns.
+    pub const PREFIX: &'static str =
+        "#chrom1\tstart1\tend1\tstrand1\tchrom2\tstart2\tend2\tstrand2\tcount";
+
+    pub fn is_empty(&self) -> bool {
+        !(self.sample || self.counts || self.normal)
+    }
+
+    /// Number of optional columns.
+    pub fn len(&self) -> usize {
+        self.sample as usize + 3 * self.counts as usize + self.normal as usize
+    }
+
+    /// Header line naming the columns, without the verbose ones.
+    pub fn header(&self) -> String {
+        let mut line = Self::PREFIX.to_string();
+        if self.sample {
+            line.push_str("\tsample");
+        }
+        if self.counts {
+            line.push_str("\tref\talt\tvaf");
+        }
+        if self.normal {
+            line.push_str("\tnormal");
+        }
+        line
+    }
+
+    /// The columns named by a header line, `None` for another line.
+    pub fn from_header(line: &str) -> Option<BedColumns> {
+        let names = line.trim_end().strip_prefix(Self::PREFIX)?;
+        let has = |name: &str| names.split('\t').any(|x| x == name);
+        Some(BedColumns {
+            sample: has("sample"),
+            counts: has("ref"),
+            normal: has("normal"),
+        })
+    }
+}
+
+/// # Line of the BED output read back
+///
+/// The type of the signal is only written in `--verbose` mode. The optional
+/// columns are skipped as given by [`BedColumns`], read from the header of
+/// the output, `FromStr` parses a line without them. `sv_type` and
+/// `orientation` are set for split reads and discordant pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalRecord {
+    pub left: Side,
+    pub right: Side,
+    /// 9th column: adjacent pairs of a split read, merged events of a CIGAR deletion, 1 otherwise
+    pub count: u32,
+    pub sample: Option<String>,
+    pub signal: SignalType,
+    pub read: String,
+    pub sv_type: Option<SplitType>,
+    pub orientation: Option<[char; 2]>,
+}
+
+impl FromStr for SignalRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SignalRecord::parse(s, &BedColumns::default())
+    }
+}
+
+impl SignalRecord {
+    /// Parse a line with the optional `columns`.
+    pub fn parse(s: &str, columns: &BedColumns) -> Result<SignalRecord, String> {
+        let fields: Vec<&str> = s.trim_end().split('\t').collect();
+        if fields.len() < 9 {
+            return Err(format!("Not an excord-lr BED line: {}", s));
+        }
+        let int = |x: &str| {
+            x.parse::<i64>()
+                .map_err(|_| format!("Invalid number {}: {}", x, s))
+        };
+        let side = |i: usize| -> Result<Side, String> {
+            Ok(Side {
+                chrom: fields[i].to_string(),
+                start: int(fields[i + 1])?,
+                end: int(fields[i + 2])?,
+                strand: int(fields[i + 3])? as i32,
+            })
+        };
+        let at = 9 + columns.len();
+        let Some(signal) = fields.get(at).and_then(|x| x.parse::<SignalType>().ok()) else {
+            return Err(format!(
+                "No signal type in column {}, the file must be written with --verbose: {}",
+                at + 1,
+                s
+            ));
+        };
+        let sample = columns.sample.then(|| fields[9].to_string());
+        let rest = &fields[at..];
+        let field = |tag: &str| rest.iter().find_map(|x| x.strip_prefix(tag));
+        Ok(SignalRecord {
+            left: side(0)?,
+            right: side(4)?,
+            count: int(fields[8])? as u32,
+            sample: sample.filter(|x| x != "."),
+            signal,
+            read: rest.get(1).unwrap_or(&"").to_string(),
+            sv_type: field("sv:").map(|x| x.parse()).transpose()?,
+            orientation: field("ori:").and_then(|x| {
+                let mut chars = x.chars();
+                Some([chars.next()?, chars.next()?])
+            }),
+        })
+    }
+}
+
+/// Open a BED output, plain or BGZF compressed, `-` is the standard input.
+pub fn open_bed(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let reader = if path == Path::new("-") {
+        bgzf::Reader::from_stdin()
+    } else {
+        bgzf::Reader::from_path(path)
+    }
+    .map_err(io::Error::other)?;
+    Ok(Box::new(BufReader::new(reader)))
+}
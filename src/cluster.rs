@@ -0,0 +1,506 @@
+use std::{collections::HashSet, fmt, io, str::FromStr};
+
+use crate::{
+    bed_record::SignalRecord,
+    signal::{SignalType, SplitType},
+    writer::check_interval,
+};
+
+/// # Type of a clustered SV call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SvType {
+    Del,
+    Ins,
+    Dup,
+    Inv,
+    Tra,
+}
+
+impl SvType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SvType::Del => "DEL",
+            SvType::Ins => "INS",
+            SvType::Dup => "DUP",
+            SvType::Inv => "INV",
+            SvType::Tra => "TRA",
+        }
+    }
+}
+
+impl fmt::Display for SvType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SvType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            SvType::Del,
+            SvType::Ins,
+            SvType::Dup,
+            SvType::Inv,
+            SvType::Tra,
+        ]
+        .into_iter()
+        .find(|x| x.as_str() == s)
+        .ok_or_else(|| format!("Unknown SV type: {}", s))
+    }
+}
+
+impl From<SplitType> for SvType {
+    fn from(x: SplitType) -> Self {
+        match x {
+            SplitType::Del => SvType::Del,
+            SplitType::Dup => SvType::Dup,
+            SplitType::Inv => SvType::Inv,
+            SplitType::Tra => SvType::Tra,
+        }
+    }
+}
+
+/// # Breakpoints of one typed signal
+///
+/// `pos1` is on the contig of the left region, `pos2` on the contig of the
+/// right one, both 0-based. `size` is the SV length, `None` when the signal
+/// does not tell it(clip insertions and translocations).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoints {
+    pub sv_type: SvType,
+    pub chrom1: String,
+    pub pos1: i64,
+    pub chrom2: String,
+    pub pos2: i64,
+    pub size: Option<i64>,
+    pub read: String,
+}
+
+impl Breakpoints {
+    /// # Breakpoints of a signal of the BED output
+    ///
+    /// - CIGAR deletion: end of the left region to start of the right region
+    /// - CIGAR insertion: end of the left region, the length is the length of the right region
+    /// - clip insertion: end of the left region, no length
+    /// - split read: the junction ends of the two segments given by the orientation
+    ///
+    /// `None` for clips, discordant pairs and split reads without SV type.
+    pub fn from_record(x: &SignalRecord) -> Option<Breakpoints> {
+        let (sv_type, pos1, pos2) = match x.signal {
+            SignalType::CigarDel => (SvType::Del, x.left.end, x.right.start),
+            SignalType::CigarIns => (SvType::Ins, x.left.end, x.left.end),
+            SignalType::ClipInsOneSegment | SignalType::ClipInsTwoSegment => {
+                (SvType::Ins, x.left.end, x.left.end)
+            }
+            SignalType::Split => {
+                let [o1, o2] = x.orientation?;
+                let junction = |o: char, start: i64, end: i64| if o == '+' { end } else { start };
+                (
+                    SvType::from(x.sv_type?),
+                    junction(o1, x.left.start, x.left.end),
+                    junction(o2, x.right.start, x.right.end),
+                )
+            }
+            SignalType::Clip | SignalType::Discordant => return None,
+        };
+        let size = match x.signal {
+            SignalType::CigarIns => Some(x.right.end - x.right.start),
+            SignalType::ClipInsOneSegment | SignalType::ClipInsTwoSegment => None,
+            _ if sv_type == SvType::Tra => None,
+            _ => Some((pos2 - pos1).abs()),
+        };
+        Some(Breakpoints {
+            sv_type,
+            chrom1: x.left.chrom.clone(),
+            pos1,
+            chrom2: x.right.chrom.clone(),
+            pos2,
+            size,
+            read: x.read.clone(),
+        })
+    }
+}
+
+/// # Options of the clustering
+#[derive(Debug, Clone)]
+pub struct ClusterOptions {
+    /// Maximal distance of a breakpoint to the mean breakpoint of a cluster
+    pub max_distance: i64,
+    /// Minimal ratio of the smaller to the larger size, signals without size always match
+    pub min_size_similarity: f64,
+    /// Minimal number of distinct reads of a call
+    pub min_support: usize,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        ClusterOptions {
+            max_distance: 500,
+            min_size_similarity: 0.7,
+            min_support: 2,
+        }
+    }
+}
+
+/// # Candidate SV supported by several reads
+///
+/// `pos1`/`pos2` are the median breakpoints and `ci1`/`ci2` the interval of
+/// the breakpoints of the members, 0-based and half-open. `size` is the median
+/// of the known sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvCall {
+    pub sv_type: SvType,
+    pub chrom1: String,
+    pub pos1: i64,
+    pub ci1: (i64, i64),
+    pub chrom2: String,
+    pub pos2: i64,
+    pub ci2: (i64, i64),
+    pub size: Option<i64>,
+    /// Number of distinct reads
+    pub support: usize,
+    /// Number of signals, a read may have several
+    pub signals: usize,
+}
+
+impl SvCall {
+    /// Header line of the BEDPE-like output.
+    pub const HEADER: &'static str =
+        "#chrom1\tstart1\tend1\tchrom2\tstart2\tend2\ttype\tpos1\tpos2\tsize\treads\tsignals";
+
+    /// BEDPE-like line, the regions are the confidence intervals.
+    pub fn write_bed(&self, mut w: impl io::Write) -> io::Result<()> {
+        check_interval(&self.chrom1, self.ci1.0, self.ci1.1, i64::MAX, "BED")?;
+        check_interval(&self.chrom2, self.ci2.0, self.ci2.1, i64::MAX, "BED")?;
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.chrom1,
+            self.ci1.0,
+            self.ci1.1,
+            self.chrom2,
+            self.ci2.0,
+            self.ci2.1,
+            self.sv_type,
+            self.pos1,
+            self.pos2,
+            self.size
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| ".".to_string()),
+            self.support,
+            self.signals
+        )
+    }
+}
+
+struct Cluster {
+    members: Vec<Breakpoints>,
+    sum1: i64,
+    sum2: i64,
+    /// largest `pos1` of the members
+    max_pos1: i64,
+    /// known sizes of the members, sorted, for the median without sorting on each check
+    sizes: Vec<i64>,
+}
+
+impl Cluster {
+    fn new(x: Breakpoints) -> Cluster {
+        Cluster {
+            sum1: x.pos1,
+            sum2: x.pos2,
+            max_pos1: x.pos1,
+            sizes: x.size.into_iter().collect(),
+            members: vec![x],
+        }
+    }
+
+    fn accepts(&self, x: &Breakpoints, opts: &ClusterOptions) -> bool {
+        let first = &self.members[0];
+        let n = self.members.len() as i64;
+        if first.sv_type != x.sv_type || first.chrom2 != x.chrom2 {
+            return false;
+        }
+        if (x.pos1 - self.sum1 / n).abs() > opts.max_distance
+            || (x.pos2 - self.sum2 / n).abs() > opts.max_distance
+        {
+            return false;
+        }
+        match (self.size(), x.size) {
+            (Some(a), Some(b)) if a > 0 && b > 0 => {
+                a.min(b) as f64 / a.max(b) as f64 >= opts.min_size_similarity
+            }
+            _ => true,
+        }
+    }
+
+    fn add(&mut self, x: Breakpoints) {
+        self.sum1 += x.pos1;
+        self.sum2 += x.pos2;
+        self.max_pos1 = self.max_pos1.max(x.pos1);
+        if let Some(size) = x.size {
+            let i = self.sizes.partition_point(|y| *y <= size);
+            self.sizes.insert(i, size);
+        }
+        self.members.push(x);
+    }
+
+    /// Lower median of the known sizes.
+    fn size(&self) -> Option<i64> {
+        (!self.sizes.is_empty()).then(|| self.sizes[(self.sizes.len() - 1) / 2])
+    }
+
+    fn into_call(self) -> SvCall {
+        let mut pos1: Vec<i64> = self.members.iter().map(|x| x.pos1).collect();
+        let mut pos2: Vec<i64> = self.members.iter().map(|x| x.pos2).collect();
+        let reads: HashSet<&str> = self.members.iter().map(|x| x.read.as_str()).collect();
+        let ci = |x: &[i64]| (*x.iter().min().unwrap(), *x.iter().max().unwrap() + 1);
+        let first = &self.members[0];
+        SvCall {
+            sv_type: first.sv_type,
+            chrom1: first.chrom1.clone(),
+            ci1: ci(&pos1),
+            pos1: median(&mut pos1).unwrap(),
+            chrom2: first.chrom2.clone(),
+            ci2: ci(&pos2),
+            pos2: median(&mut pos2).unwrap(),
+            size: self.size(),
+            support: reads.len(),
+            signals: self.members.len(),
+        }
+    }
+}
+
+/// Lower median.
+fn median(x: &mut [i64]) -> Option<i64> {
+    if x.is_empty() {
+        return None;
+    }
+    x.sort_unstable();
+    Some(x[(x.len() - 1) / 2])
+}
+
+/// # Streaming clustering of sorted signals
+///
+/// The signals must come sorted by contig and start of the left region, like
+/// the output of `--sort`. A signal joins the first open cluster of the same
+/// SV type and second contig whose mean breakpoints are within
+/// `max_distance` and whose size is similar, otherwise it starts a new
+/// cluster. A cluster is closed once the signals are past its last
+/// breakpoint by more than `max_distance`, so only the clusters around the
+/// current position are kept in memory. Calls come out sorted by the start
+/// of their first confidence interval.
+pub struct Clusterer {
+    opts: ClusterOptions,
+    open: Vec<Cluster>,
+    closed: Vec<SvCall>,
+    chrom: Option<String>,
+    done: HashSet<String>,
+    last_start: i64,
+}
+
+impl Clusterer {
+    pub fn new(opts: ClusterOptions) -> Clusterer {
+        Clusterer {
+            opts,
+            open: vec![],
+            closed: vec![],
+            chrom: None,
+            done: HashSet::new(),
+            last_start: 0,
+        }
+    }
+
+    /// Add a signal, the calls which can not change anymore are appended to
+    /// `out`. Fails if the signals are not sorted.
+    pub fn push(&mut self, x: &SignalRecord, out: &mut Vec<SvCall>) -> Result<(), String> {
+        let start = x.left.start;
+        if self.chrom.as_deref() != Some(x.left.chrom.as_str()) {
+            if self.done.contains(&x.left.chrom) {
+                return Err(format!(
+                    "The signals are not sorted, {} is not contiguous",
+                    x.left.chrom
+                ));
+            }
+            self.flush(i64::MAX, out);
+            if let Some(chrom) = self.chrom.replace(x.left.chrom.clone()) {
+                self.done.insert(chrom);
+            }
+        } else if start < self.last_start {
+            return Err(format!(
+                "The signals are not sorted, {}:{} after {}",
+                x.left.chrom, start, self.last_start
+            ));
+        }
+        self.last_start = start;
+        // a breakpoint is never before the start of the left region.
+        self.flush(start - self.opts.max_distance, out);
+
+        let Some(bp) = Breakpoints::from_record(x) else {
+            return Ok(());
+        };
+        match self.open.iter_mut().find(|c| c.accepts(&bp, &self.opts)) {
+            Some(cluster) => cluster.add(bp),
+            None => self.open.push(Cluster::new(bp)),
+        }
+        Ok(())
+    }
+
+    /// Close every cluster and append the remaining calls to `out`.
+    pub fn finish(mut self, out: &mut Vec<SvCall>) {
+        self.flush(i64::MAX, out);
+    }
+
+    /// Close the clusters whose breakpoints are all before `before`, then
+    /// release the closed calls starting before every open cluster.
+    fn flush(&mut self, before: i64, out: &mut Vec<SvCall>) {
+        let (closing, open): (Vec<Cluster>, Vec<Cluster>) = std::mem::take(&mut self.open)
+            .into_iter()
+            .partition(|c| c.max_pos1 < before);
+        self.open = open;
+        self.closed.extend(
+            closing
+                .into_iter()
+                .map(Cluster::into_call)
+                .filter(|x| x.support >= self.opts.min_support),
+        );
+        let first_open = self
+            .open
+            .iter()
+            .flat_map(|c| c.members.iter().map(|x| x.pos1))
+            .min()
+            .unwrap_or(i64::MAX);
+        self.closed.sort_by_key(|x| (x.ci1.0, x.ci1.1));
+        let n = self.closed.partition_point(|x| x.ci1.0 < first_open);
+        out.extend(self.closed.drain(..n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str) -> SignalRecord {
+        line.replace(' ', "\t").parse().unwrap()
+    }
+
+    /// CIGAR deletion of `read` from `pos` to `pos + size`.
+    fn del(read: &str, pos: i64, size: i64) -> SignalRecord {
+        record(&format!(
+            "1 {} {} 1 1 {} {} 1 1 cigar-del {}",
+            pos - 100,
+            pos,
+            pos + size,
+            pos + size + 100,
+            read
+        ))
+    }
+
+    fn cluster(records: &[SignalRecord], opts: ClusterOptions) -> Result<Vec<SvCall>, String> {
+        let mut clusterer = Clusterer::new(opts);
+        let mut out = vec![];
+        for x in records {
+            clusterer.push(x, &mut out)?;
+        }
+        clusterer.finish(&mut out);
+        Ok(out)
+    }
+
+    #[test]
+    fn breakpoints_of_the_signals() {
+        let x = Breakpoints::from_record(&del("r", 1000, 500)).unwrap();
+        assert_eq!(
+            (x.sv_type, x.pos1, x.pos2, x.size),
+            (SvType::Del, 1000, 1500, Some(500))
+        );
+        let x = record("1 900 1000 1 1 1000 1300 1 1 cigar-ins r");
+        let x = Breakpoints::from_record(&x).unwrap();
+        assert_eq!(
+            (x.sv_type, x.pos1, x.pos2, x.size),
+            (SvType::Ins, 1000, 1000, Some(300))
+        );
+        let x = record("1 900 1000 1 2 5000 5100 -1 1 split r sv:INV ori:++");
+        let x = Breakpoints::from_record(&x).unwrap();
+        assert_eq!(
+            (x.sv_type, x.pos1, x.pos2, x.size),
+            (SvType::Inv, 1000, 5100, Some(4100))
+        );
+        let x = record("1 900 1000 1 2 5000 5100 -1 1 split r sv:TRA ori:+-");
+        assert_eq!(Breakpoints::from_record(&x).unwrap().size, None);
+        // no SV type without --verbose columns of the split read.
+        assert!(
+            Breakpoints::from_record(&record("1 900 1000 1 1 5000 5100 1 1 split r")).is_none()
+        );
+        assert!(Breakpoints::from_record(&record("1 900 1000 1 1 1000 1000 1 1 clip r")).is_none());
+    }
+
+    #[test]
+    fn clusters_close_signals_of_several_reads() {
+        let calls = cluster(
+            &[
+                del("r1", 1000, 1000),
+                del("r2", 1010, 1100),
+                del("r1", 1030, 900),
+                del("r3", 9000, 1000),
+            ],
+            ClusterOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(calls.len(), 1);
+        let x = &calls[0];
+        assert_eq!((x.pos1, x.ci1), (1010, (1000, 1031)));
+        assert_eq!((x.pos2, x.ci2), (2000, (1930, 2111)));
+        // lower median of 900, 1000 and 1100, whatever the order they came in.
+        assert_eq!(x.size, Some(1000));
+        assert_eq!((x.support, x.signals), (2, 3));
+    }
+
+    #[test]
+    fn dissimilar_sizes_are_separate_calls() {
+        let opts = ClusterOptions {
+            min_support: 1,
+            ..Default::default()
+        };
+        let calls = cluster(&[del("r1", 1000, 1000), del("r2", 1010, 600)], opts).unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!((calls[0].size, calls[1].size), (Some(1000), Some(600)));
+        let opts = ClusterOptions {
+            min_support: 1,
+            min_size_similarity: 0.5,
+            ..Default::default()
+        };
+        let calls = cluster(&[del("r1", 1000, 1000), del("r2", 1010, 600)], opts).unwrap();
+        assert_eq!(calls.len(), 1);
+    }
+
+    #[test]
+    fn calls_come_out_sorted() {
+        let opts = ClusterOptions {
+            min_support: 1,
+            ..Default::default()
+        };
+        // a long deletion keeps its cluster open while the next ones are closed.
+        let calls = cluster(
+            &[
+                del("r1", 1000, 5000),
+                del("r2", 1100, 100),
+                del("r3", 3000, 100),
+            ],
+            opts,
+        )
+        .unwrap();
+        let starts: Vec<i64> = calls.iter().map(|x| x.ci1.0).collect();
+        assert_eq!(starts, vec![1000, 1100, 3000]);
+    }
+
+    #[test]
+    fn unsorted_signals_are_an_error() {
+        let opts = ClusterOptions::default;
+        assert!(cluster(&[del("r1", 5000, 100), del("r2", 1000, 100)], opts()).is_err());
+        let mut other = del("r2", 1000, 100);
+        other.left.chrom = "2".to_string();
+        let records = [del("r1", 1000, 100), other, del("r3", 5000, 100)];
+        assert!(cluster(&records, opts()).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::utils::clean_chrom;
+
+/// # How the contig names of the BAM are written in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContigNaming {
+    /// As in the BAM header
+    Keep,
+    /// Remove a leading `chr`, `chr1` -> `1`
+    #[default]
+    StripChr,
+    /// Add `chr` to the names without it, `1` -> `chr1`
+    AddChr,
+    /// Map the names through a two-column alias file
+    Alias,
+}
+
+impl ContigNaming {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContigNaming::Keep => "keep",
+            ContigNaming::StripChr => "strip-chr",
+            ContigNaming::AddChr => "add-chr",
+            ContigNaming::Alias => "alias",
+        }
+    }
+}
+
+impl fmt::Display for ContigNaming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ContigNaming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ContigNaming::Keep,
+            ContigNaming::StripChr,
+            ContigNaming::AddChr,
+            ContigNaming::Alias,
+        ]
+        .into_iter()
+        .find(|x| x.as_str() == s)
+        .ok_or_else(|| format!("Unknown contig naming: {}", s))
+    }
+}
+
+/// # Contig naming policy
+///
+/// Renames the contigs of the signals and of the VCF header. With
+/// [`ContigNaming::Alias`] a name missing from the alias file can not be
+/// mapped, nor with [`ContigNaming::StripChr`] a contig named `chr`
+/// only(see [`clean_chrom`]).
+/// [`ContigNames::check`] catches them before the extraction.
+#[derive(Debug, Clone, Default)]
+pub struct ContigNames {
+    naming: ContigNaming,
+    aliases: HashMap<String, String>,
+}
+
+impl ContigNames {
+    /// A policy without alias file, use [`ContigNames::from_alias_file`] for [`ContigNaming::Alias`].
+    pub fn new(naming: ContigNaming) -> ContigNames {
+        ContigNames {
+            naming,
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// Read an alias file: the name in the BAM and the name in the output,
+    /// separated by tabs or spaces. Empty lines and lines starting with `#`
+    /// are skipped.
+    pub fn from_alias_file(path: &Path) -> Result<ContigNames, String> {
+        let f = File::open(path).map_err(|e| e.to_string())?;
+        let mut aliases = HashMap::new();
+        for (i, line) in BufReader::new(f).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(from), Some(to), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(format!(
+                    "line {} does not have two columns: {}",
+                    i + 1,
+                    line
+                ));
+            };
+            if aliases.insert(from.to_string(), to.to_string()).is_some() {
+                return Err(format!("line {}: {} is given twice", i + 1, from));
+            }
+        }
+        Ok(ContigNames {
+            naming: ContigNaming::Alias,
+            aliases,
+        })
+    }
+
+    pub fn naming(&self) -> ContigNaming {
+        self.naming
+    }
+
+    /// Name in the output, `None` if the alias file does not have it or the
+    /// name is `chr` alone with `strip-chr`.
+    pub fn get<'a>(&'a self, name: &'a str) -> Option<Cow<'a, str>> {
+        match self.naming {
+            ContigNaming::Keep => Some(Cow::Borrowed(name)),
+            ContigNaming::StripChr => clean_chrom(name).map(Cow::Borrowed),
+            ContigNaming::AddChr if name.starts_with("chr") => Some(Cow::Borrowed(name)),
+            ContigNaming::AddChr => Some(Cow::Owned(format!("chr{}", name))),
+            ContigNaming::Alias => self.aliases.get(name).map(|x| Cow::Borrowed(x.as_str())),
+        }
+    }
+
+    /// Name in the output, unchanged if it can not be mapped.
+    pub fn rename<'a>(&'a self, name: &'a str) -> Cow<'a, str> {
+        self.get(name).unwrap_or(Cow::Borrowed(name))
+    }
+
+    /// # Check that the policy maps every contig
+    ///
+    /// Fails on the first name that can not be mapped(not in the alias file,
+    /// or `chr` alone with `strip-chr`) and when two names are
+    /// mapped to the same one, e.g. `chr1` and `1` with `strip-chr`.
+    pub fn check<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+        let mut seen: HashMap<String, &str> = HashMap::new();
+        for name in names {
+            let renamed = self.get(name).ok_or_else(|| match self.naming {
+                ContigNaming::StripChr => format!(
+                    "contig {} has no name left without chr, use --contig-naming keep or --contig-alias",
+                    name
+                ),
+                _ => format!("contig {} is not in the alias file", name),
+            })?;
+            if let Some(other) = seen.insert(renamed.to_string(), name) {
+                return Err(format!(
+                    "contigs {} and {} are both named {} ({})",
+                    other, name, renamed, self.naming
+                ));
+            }
+        }
+        Ok(())
+    }
+}
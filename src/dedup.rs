@@ -0,0 +1,220 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::signal::SvSignal;
+
+/// The record a signal has been extracted from: tid, position and flag.
+type Origin = (i32, i64, u16);
+
+/// Name of the read and its first/last segment flags(`0x40`/`0x80`), the
+/// two mates of a pair have their own chain.
+type ReadKey = (String, u16);
+
+#[derive(Debug)]
+struct SeenRead {
+    /// the record whose split chain is kept
+    first: Origin,
+    last: Origin,
+    /// number of records of the read still expected
+    remaining: usize,
+    /// tid and start of the last segment of the read, its records are all
+    /// seen once a sorted input is past it
+    until: (i32, i64),
+}
+
+/// # Emit the split chain of each read only once
+///
+/// Every record of a split read(primary and supplementary) carries an `SA` tag
+/// and rebuilds the same chain of segments. The chain of the first record seen
+/// (the primary one if it comes first) is kept, the split-read and clip
+/// insertion signals of the other records of the same read are dropped. The
+/// mates of a pair are different reads.
+///
+/// A read is forgotten once all of its records have been seen, or once a
+/// coordinate-sorted input is past the start of its last segment: a record
+/// dropped by the filters or outside the regions never comes. When the input
+/// goes backwards it is not sorted and the reads are only forgotten once all
+/// of their records have been seen.
+#[derive(Debug, Default)]
+pub struct SplitDedup {
+    seen: HashMap<ReadKey, SeenRead>,
+    /// tid of each contig, by its name in the signals
+    tids: HashMap<String, i32>,
+    /// reads by the start of their last segment
+    expiry: BinaryHeap<Reverse<((i32, i64), ReadKey)>>,
+    /// position of the last record, `None` once the input is found unsorted
+    position: Option<(i32, i64)>,
+    /// Number of signals dropped as duplicates
+    pub suppressed: u64,
+}
+
+impl SplitDedup {
+    /// `contigs` are the names of the contigs in the signals, in the order of the header.
+    pub fn new(contigs: &[String]) -> SplitDedup {
+        SplitDedup {
+            tids: contigs
+                .iter()
+                .enumerate()
+                .map(|(tid, x)| (x.clone(), tid as i32))
+                .collect(),
+            position: Some((-1, -1)),
+            ..Default::default()
+        }
+    }
+
+    /// tid and start of the last segment of the signal, unknown contigs sort last.
+    fn last_segment(&self, x: &SvSignal) -> (i32, i64) {
+        let tid = |chrom: &str| self.tids.get(chrom).copied().unwrap_or(i32::MAX);
+        match x {
+            SvSignal::Split { left, right, .. } => {
+                (tid(&left.chrom), left.start).max((tid(&right.chrom), right.start))
+            }
+            _ => match x.event() {
+                Some(e) => (tid(&e.lchrom), e.lstart).max((tid(&e.rchrom), e.rstart)),
+                None => (i32::MAX, i64::MAX),
+            },
+        }
+    }
+
+    /// Forget the reads whose last segment is before `at`, or every expiry if
+    /// the input goes backwards.
+    fn expire(&mut self, at: (i32, i64)) {
+        let Some(position) = self.position else {
+            return;
+        };
+        if at < position {
+            self.position = None;
+            self.expiry.clear();
+            return;
+        }
+        self.position = Some(at);
+        while self.expiry.peek().is_some_and(|x| x.0 .0 < at) {
+            let Reverse((until, key)) = self.expiry.pop().unwrap();
+            // a later signal of the first record may have moved it.
+            if self.seen.get(&key).is_some_and(|x| x.until == until) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+
+    /// Return `false` if the signal duplicates the chain of another record of the same read.
+    pub fn keep(&mut self, x: &SvSignal) -> bool {
+        // number of records that carry the chain
+        let records = match x {
+            SvSignal::Split { segments, .. } => segments + 1,
+            SvSignal::ClipInsOneSegment { segments, .. } => *segments,
+            SvSignal::ClipInsTwoSegment { .. } => 2,
+            SvSignal::CigarDel { .. }
+            | SvSignal::CigarIns { .. }
+            | SvSignal::Clip { .. }
+            | SvSignal::Discordant { .. } => return true,
+        };
+        if records < 2 {
+            return true;
+        }
+        let read = x.read();
+        let origin = (read.tid, read.pos, read.flags);
+        self.expire((read.tid, read.pos));
+        let until = self.last_segment(x);
+        let key = (read.qname.clone(), read.flags & 0xC0);
+
+        let Some(seen) = self.seen.get_mut(&key) else {
+            self.seen.insert(
+                key.clone(),
+                SeenRead {
+                    first: origin,
+                    last: origin,
+                    remaining: records - 1,
+                    until,
+                },
+            );
+            if self.position.is_some() {
+                self.expiry.push(Reverse((until, key)));
+            }
+            return true;
+        };
+        if origin == seen.first {
+            if until > seen.until {
+                seen.until = until;
+                if self.position.is_some() {
+                    self.expiry.push(Reverse((until, key)));
+                }
+            }
+            return true;
+        }
+        if origin != seen.last {
+            // first signal of another record of the read
+            seen.last = origin;
+            seen.remaining = seen.remaining.saturating_sub(1);
+        }
+        self.suppressed += 1;
+        if seen.remaining == 0 {
+            self.seen.remove(&key);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signal::{ReadInfo, SplitType},
+        utils::parse_supplementary_alignment,
+    };
+
+    /// Split signal of the record at `pos` with `flags`, between two segments given as in `SA`.
+    fn split(flags: u16, pos: i64, left: &str, right: &str) -> SvSignal {
+        SvSignal::Split {
+            read: ReadInfo {
+                qname: "pair".to_string(),
+                strand: 1,
+                flags,
+                mapq: 60,
+                tid: 0,
+                pos,
+                read_group: None,
+                sample: None,
+            },
+            left: parse_supplementary_alignment(left).unwrap(),
+            right: parse_supplementary_alignment(right).unwrap(),
+            segments: 1,
+            sv_type: SplitType::Del,
+            orientation: ['+', '-'],
+        }
+    }
+
+    #[test]
+    fn mates_keep_their_own_chain() {
+        let mut dedup = SplitDedup::new(&["1".to_string()]);
+        let mate1 = ("1,1001,+,100M100S,60,0", "1,5001,+,100S100M,60,0");
+        let mate2 = ("1,2001,+,100M100S,60,0", "1,7001,+,100S100M,60,0");
+        // primary records of both mates
+        assert!(dedup.keep(&split(0x41, 1000, mate1.0, mate1.1)));
+        assert!(dedup.keep(&split(0x81, 2000, mate2.0, mate2.1)));
+        // supplementary records of both mates
+        assert!(!dedup.keep(&split(0x841, 5000, mate1.0, mate1.1)));
+        assert!(!dedup.keep(&split(0x881, 7000, mate2.0, mate2.1)));
+        assert_eq!(dedup.suppressed, 2);
+    }
+
+    #[test]
+    fn sorted_input_forgets_reads_past_their_last_segment() {
+        let mut dedup = SplitDedup::new(&["1".to_string()]);
+        let read = ("1,1001,+,100M100S,60,0", "1,5001,+,100S100M,60,0");
+        assert!(dedup.keep(&split(0, 1000, read.0, read.1)));
+        assert_eq!(dedup.seen.len(), 1);
+        // the supplementary record at 5000 was filtered out, the input is past it.
+        assert!(dedup.keep(&split(
+            0,
+            9000,
+            "1,9001,+,100M100S,60,0",
+            "1,9501,+,100S100M,60,0"
+        )));
+        // the read was forgotten, the record at 9000 starts a new chain.
+        assert_eq!(dedup.seen[&("pair".to_string(), 0)].first.1, 9000);
+        assert_eq!(dedup.suppressed, 0);
+    }
+}
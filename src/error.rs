@@ -0,0 +1,159 @@
+use std::{error::Error, fmt, io, path::PathBuf, str::FromStr};
+
+/// # Errors of excord-lr
+///
+/// Each variant has its own exit code, see [`ExcordError::exit_code`].
+#[derive(Debug)]
+pub enum ExcordError {
+    /// Invalid option or input path
+    Usage(String),
+    /// The BAM/CRAM, its index or the reference can not be opened
+    Open { path: PathBuf, msg: String },
+    /// Reading the alignments failed, e.g. a truncated BAM. `after` is the
+    /// contig and 0-based position of the last record read.
+    Read {
+        after: Option<(String, i64)>,
+        source: rust_htslib::errors::Error,
+    },
+    /// A record that can not be processed, e.g. a malformed SA tag
+    Record {
+        qname: String,
+        contig: String,
+        pos: i64,
+        msg: String,
+    },
+    /// Writing the output failed
+    Write { path: PathBuf, source: io::Error },
+    /// An error of one input of a run over several inputs
+    Input {
+        path: PathBuf,
+        source: Box<ExcordError>,
+    },
+}
+
+impl ExcordError {
+    /// Exit code of the command line tool, 2 is used by clap for invalid arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ExcordError::Usage(_) => 1,
+            ExcordError::Open { .. } => 3,
+            ExcordError::Read { .. } => 4,
+            ExcordError::Record { .. } => 5,
+            ExcordError::Write { .. } => 6,
+            ExcordError::Input { source, .. } => source.exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for ExcordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcordError::Usage(msg) => f.write_str(msg),
+            ExcordError::Open { path, msg } => {
+                write!(f, "Can not open {}: {}", path.display(), msg)
+            }
+            ExcordError::Read {
+                after: Some((contig, pos)),
+                source,
+            } => write!(
+                f,
+                "Failed to read the alignments after {}:{}: {}",
+                contig,
+                pos + 1,
+                source
+            ),
+            ExcordError::Read {
+                after: None,
+                source,
+            } => write!(f, "Failed to read the alignments: {}", source),
+            ExcordError::Record {
+                qname,
+                contig,
+                pos,
+                msg,
+            } => write!(
+                f,
+                "Invalid record {} at {}:{}: {}",
+                qname,
+                contig,
+                pos + 1,
+                msg
+            ),
+            ExcordError::Write { path, source } => {
+                write!(f, "Failed to write {}: {}", path.display(), source)
+            }
+            ExcordError::Input { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for ExcordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExcordError::Read { source, .. } => Some(source),
+            ExcordError::Write { source, .. } => Some(source),
+            ExcordError::Input { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// # What to do with a record that can not be processed
+///
+/// Errors reading the input or writing the output always abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// Stop with the error
+    #[default]
+    Abort,
+    /// Drop the record silently
+    Skip,
+    /// Drop the record and report it on stderr
+    Warn,
+}
+
+impl OnError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnError::Abort => "abort",
+            OnError::Skip => "skip",
+            OnError::Warn => "warn",
+        }
+    }
+}
+
+impl fmt::Display for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [OnError::Abort, OnError::Skip, OnError::Warn]
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| format!("Unknown error policy: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_error_keeps_the_code_of_its_source() {
+        let e = ExcordError::Input {
+            path: PathBuf::from("a.bam"),
+            source: Box::new(ExcordError::Write {
+                path: PathBuf::from("a.bed"),
+                source: io::Error::other("disk full"),
+            }),
+        };
+        assert_eq!(e.exit_code(), 6);
+        assert_eq!(e.to_string(), "a.bam: Failed to write a.bed: disk full");
+        assert!(e.source().unwrap().is::<ExcordError>());
+    }
+}
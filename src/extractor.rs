@@ -0,0 +1,1077 @@
+use bio_types::{genome::AbstractInterval, strand::ReqStrand};
+use rust_htslib::{
+    bam::{
+        record::{Aux, Cigar},
+        HeaderView, Read, Record,
+    },
+    errors::Result as HtsResult,
+};
+use std::{cmp::Ordering, collections::VecDeque, sync::Arc};
+
+use crate::{
+    aligments_event::{merge_alignment_events, AlignEventType, AlignmentEvent},
+    contig_naming::ContigNames,
+    error::{ExcordError, OnError},
+    read_group::ReadGroups,
+    signal::{ClipSide, ReadInfo, SplitType, SvSignal},
+    split_read_event::SplitReadEvent,
+    te::TeLibrary,
+    utils::*,
+};
+
+/// # Options of the signal extraction
+///
+/// Mirrors the command line options of `excord-lr`. `Default` gives the same
+/// values as the CLI defaults.
+#[derive(Debug, Clone)]
+pub struct ExtractorOptions {
+    /// Minimal MapQ
+    pub mapq: u8,
+    /// Exclude Flags
+    pub exclude_flag: u16,
+    /// Exclude Secondary Alignment
+    pub exclude_secondary: bool,
+    /// Exclude Unmapped Alignment
+    pub exclude_unmapped: bool,
+    /// Minimal length to define an SV events in CIGAR
+    pub indel_min: u32,
+    /// Threshold to merge two adjacent events
+    pub merge_min: u32,
+    /// Minimal length of hard-clip and soft-clip to define a large insertion signal
+    pub ins_clip_min: u32,
+    /// Not merge adjacent CIGAR deletions
+    pub not_merge: bool,
+    /// Only report split-read event
+    pub split_only: bool,
+    /// SV types of the split-read pairs to report
+    pub split_types: Vec<SplitType>,
+    /// Percent of overlap to discard a potential false positive record
+    pub max_pct_overlap: f64,
+    /// Maximal number of SA to include a record
+    pub max_supp_alignm: usize,
+    /// Minimal soft clip of a record without `SA` to report a clip signal, `None` disables them
+    pub clip_min: Option<u32>,
+    /// Report discordant pairs of short reads, the maximal outer distance of a
+    /// concordant pair. `None` for long reads
+    pub max_insert: Option<i64>,
+    /// Capture the inserted sequence of the insertion signals
+    pub ins_seq: bool,
+    /// Classify the inserted sequences against this TE library, needs `ins_seq`
+    pub te_library: Option<Arc<TeLibrary>>,
+    /// Names of the contigs in the output
+    pub contig_names: Arc<ContigNames>,
+    /// What to do with a record that can not be processed
+    pub on_error: OnError,
+    /// Report the records dropped by `exclude_flag` on stderr
+    pub debug: bool,
+}
+
+impl Default for ExtractorOptions {
+    fn default() -> Self {
+        ExtractorOptions {
+            mapq: 1,
+            exclude_flag: 1796,
+            exclude_secondary: false,
+            exclude_unmapped: false,
+            indel_min: 50,
+            merge_min: 5,
+            ins_clip_min: 1000,
+            not_merge: false,
+            split_only: false,
+            split_types: SplitType::ALL.to_vec(),
+            max_pct_overlap: 0.0,
+            max_supp_alignm: 4,
+            clip_min: None,
+            max_insert: None,
+            ins_seq: false,
+            te_library: None,
+            contig_names: Arc::new(ContigNames::default()),
+            on_error: OnError::Abort,
+            debug: false,
+        }
+    }
+}
+
+impl ExtractorOptions {
+    /// Return `true` if the record passes the MapQ and flag filters.
+    pub fn is_selected(&self, record: &Record) -> bool {
+        if self.exclude_secondary && record.is_secondary() {
+            return false;
+        }
+
+        if self.exclude_unmapped && record.is_unmapped() {
+            return false;
+        }
+
+        if record.mapq() < self.mapq {
+            return false;
+        }
+
+        // == 0 means not match with flag.
+        if record.flags() & self.exclude_flag != 0u16 {
+            if self.debug {
+                eprintln!(
+                    "found filtered flag: {}",
+                    String::from_utf8_lossy(record.qname())
+                );
+            }
+            return false;
+        }
+        true
+    }
+
+    /// # Filters applied to the mate of a paired record
+    ///
+    /// The mate is judged from what the record tells of it: its MapQ is the
+    /// `MQ` tag, passing when absent, and its flags are those of the record
+    /// with the mate and read 1/2 bits swapped. The mate is assumed to be a
+    /// primary alignment with the same duplicate and QC flags.
+    pub fn is_mate_selected(&self, record: &Record) -> bool {
+        if let Ok(Aux::U8(mapq)) = record.aux(b"MQ") {
+            if mapq < self.mapq {
+                return false;
+            }
+        }
+        let flags = record.flags();
+        let swap = |from: u16, to: u16| if flags & from != 0 { to } else { 0 };
+        let mate_flags = (flags & 0x603)
+            | swap(0x8, 0x4)
+            | swap(0x4, 0x8)
+            | swap(0x20, 0x10)
+            | swap(0x10, 0x20)
+            | swap(0x80, 0x40)
+            | swap(0x40, 0x80);
+        mate_flags & self.exclude_flag == 0
+    }
+}
+
+/// # Source of alignment records
+///
+/// Implemented by every `bam::Read` and by readers restricted to regions,
+/// e.g. [`RegionReader`](crate::region::RegionReader).
+pub trait RecordSource {
+    fn read_record(&mut self, record: &mut Record) -> Option<HtsResult<()>>;
+
+    fn header_view(&self) -> &HeaderView;
+}
+
+impl<R: Read> RecordSource for R {
+    fn read_record(&mut self, record: &mut Record) -> Option<HtsResult<()>> {
+        self.read(record)
+    }
+
+    fn header_view(&self) -> &HeaderView {
+        self.header()
+    }
+}
+
+/// # Reader with records read ahead
+///
+/// The records read by [`Prefetched::read_ahead`] are given back first, then
+/// the rest of the reader. Used to look at the start of an input that can
+/// not be read twice, e.g. the standard input.
+pub struct Prefetched<R: RecordSource> {
+    records: VecDeque<Record>,
+    reader: R,
+}
+
+impl<R: RecordSource> Prefetched<R> {
+    pub fn new(reader: R) -> Prefetched<R> {
+        Prefetched {
+            records: VecDeque::new(),
+            reader,
+        }
+    }
+
+    /// Read records until `more` returns `false` for the last one or the end of the input.
+    pub fn read_ahead(&mut self, mut more: impl FnMut(&Record) -> bool) -> HtsResult<()> {
+        loop {
+            let mut record = Record::new();
+            match self.reader.read_record(&mut record) {
+                Some(Ok(())) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            }
+            let next = more(&record);
+            self.records.push_back(record);
+            if !next {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The records read ahead and not given back yet.
+    pub fn records(&self) -> &VecDeque<Record> {
+        &self.records
+    }
+}
+
+impl<R: RecordSource> RecordSource for Prefetched<R> {
+    fn read_record(&mut self, record: &mut Record) -> Option<HtsResult<()>> {
+        match self.records.pop_front() {
+            Some(x) => {
+                *record = x;
+                Some(Ok(()))
+            }
+            None => self.reader.read_record(record),
+        }
+    }
+
+    fn header_view(&self) -> &HeaderView {
+        self.reader.header_view()
+    }
+}
+
+/// # Streaming extractor of SV signals
+///
+/// Reads records from any `bam::Read` (or other [`RecordSource`]) and yields
+/// the signals of each record in the order of the input.
+///
+/// A record that can not be processed is handled by `on_error` of the options,
+/// an error reading the input always ends the iteration with an `Err`.
+pub struct SignalExtractor<R: RecordSource> {
+    reader: R,
+    record: Record,
+    opts: ExtractorOptions,
+    read_groups: ReadGroups,
+    pending: VecDeque<SvSignal>,
+    /// tid and position of the last record read
+    last_pos: Option<(i32, i64)>,
+    skipped: u64,
+    done: bool,
+}
+
+impl<R: RecordSource> SignalExtractor<R> {
+    pub fn new(reader: R, opts: ExtractorOptions) -> SignalExtractor<R> {
+        SignalExtractor {
+            read_groups: ReadGroups::from_header(reader.header_view()),
+            reader,
+            record: Record::new(),
+            opts,
+            pending: VecDeque::new(),
+            last_pos: None,
+            skipped: 0,
+            done: false,
+        }
+    }
+
+    pub fn options(&self) -> &ExtractorOptions {
+        &self.opts
+    }
+
+    /// Number of records dropped by the `skip` and `warn` error policies.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    fn read_error(&self, source: rust_htslib::errors::Error) -> ExcordError {
+        let header = self.reader.header_view();
+        let after = self.last_pos.map(|(tid, pos)| {
+            let contig = match tid {
+                tid if tid >= 0 && (tid as u32) < header.target_count() => {
+                    String::from_utf8_lossy(header.tid2name(tid as u32)).into_owned()
+                }
+                _ => "*".to_string(),
+            };
+            (contig, pos)
+        });
+        ExcordError::Read { after, source }
+    }
+
+    /// Give the underlying reader back.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: RecordSource> Iterator for SignalExtractor<R> {
+    type Item = Result<SvSignal, ExcordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(x) = self.pending.pop_front() {
+                return Some(Ok(x));
+            }
+            if self.done {
+                return None;
+            }
+            match self.reader.read_record(&mut self.record) {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(self.read_error(e)));
+                }
+                None => {
+                    //exit if the last one was processed.
+                    self.done = true;
+                    return None;
+                }
+            }
+            self.last_pos = Some((self.record.tid(), self.record.pos()));
+            if !self.opts.is_selected(&self.record) {
+                continue;
+            }
+            if let Err(e) = extract_record(
+                &self.record,
+                self.reader.header_view(),
+                &self.read_groups,
+                &self.opts,
+                &mut self.pending,
+            ) {
+                match self.opts.on_error {
+                    OnError::Abort => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                    OnError::Skip => self.skipped += 1,
+                    OnError::Warn => {
+                        eprintln!("Skipped: {}", e);
+                        self.skipped += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// # Extract all signals of one record
+///
+/// The signals are appended to `out` in the order they are reported:
+/// large insertions from clips and split-read pairs, or the soft clips of a
+/// record without `SA`, then CIGAR events and the discordant pair. Nothing is
+/// appended if the record is invalid. `header` is used for the contig of the
+/// mate and `read_groups` for the sample of the read.
+pub fn extract_record(
+    record: &Record,
+    header: &HeaderView,
+    read_groups: &ReadGroups,
+    opts: &ExtractorOptions,
+    out: &mut VecDeque<SvSignal>,
+) -> Result<(), ExcordError> {
+    // an unplaced record has no position to report.
+    if record.tid() < 0 {
+        return Ok(());
+    }
+    let invalid = |msg: String| ExcordError::Record {
+        qname: String::from_utf8_lossy(record.qname()).into_owned(),
+        contig: record.contig().to_string(),
+        pos: record.pos(),
+        msg,
+    };
+    let qname = std::str::from_utf8(record.qname())
+        .map_err(|_| invalid("read name is not UTF-8".to_string()))?;
+    let strand = match record.strand() {
+        ReqStrand::Forward => 1,
+        ReqStrand::Reverse => -1,
+    };
+    let read_group = match record.aux(b"RG") {
+        Ok(Aux::String(x)) => Some(x.to_string()),
+        _ => None,
+    };
+    let read = ReadInfo {
+        qname: qname.to_string(),
+        strand,
+        flags: record.flags(),
+        mapq: record.mapq(),
+        tid: record.tid(),
+        pos: record.pos(),
+        sample: read_group
+            .as_deref()
+            .and_then(|x| read_groups.sample(x))
+            .map(|x| x.to_string()),
+        read_group,
+    };
+    let first = out.len();
+    // the whole read in read orientation, the clipped bases are needed. A
+    // record without SEQ(`*`) has no bases to take.
+    let read_seq = (opts.ins_seq
+        && record.seq_len() > 0
+        && !record
+            .cigar()
+            .iter()
+            .any(|x| matches!(x, Cigar::HardClip(_))))
+    .then(|| {
+        let seq = record.seq().as_bytes();
+        if strand < 0 {
+            reverse_complement(&seq)
+        } else {
+            seq
+        }
+    });
+
+    if let Ok(sa) = record.aux(b"SA") {
+        let mut alignment_vec: Vec<SplitReadEvent> = vec![SplitReadEvent::new(
+            record.contig(),
+            &record.pos(),
+            parse_cigar(&record.cigar().to_string()),
+            &strand,
+            &record.mapq(),
+            &record.cigar().to_string(),
+        )];
+
+        /* process the supplementary alignments */
+        if let Aux::String(sa) = sa {
+            let sa_list = sa.split(';').collect::<Vec<&str>>();
+
+            // the whole record is skipped, including its CIGAR events.
+            if sa_list.len() > opts.max_supp_alignm {
+                return Ok(());
+            }
+
+            for single_sa in sa_list.iter().filter(|x| !x.is_empty()) {
+                alignment_vec.push(parse_supplementary_alignment(single_sa).map_err(invalid)?);
+            }
+        } else {
+            return Err(invalid("SA is not a string".to_string()));
+        }
+        alignment_vec.sort_by(splitter_order_cmp);
+
+        extract_clip_insertions(&alignment_vec, opts, &read, read_seq.as_deref(), out);
+
+        for i in 1..alignment_vec.len() {
+            let a = &alignment_vec[i - 1];
+            let b = &alignment_vec[i];
+            let (sv_type, [a_ori, b_ori]) = classify_split(a, b);
+            if !opts.split_types.contains(&sv_type) {
+                continue;
+            }
+            let (left, right, orientation) = if alignment_pos_cmp(a, b) == Ordering::Greater {
+                (b, a, [b_ori, a_ori])
+            } else {
+                (a, b, [a_ori, b_ori])
+            };
+            out.push_back(SvSignal::Split {
+                read: read.clone(),
+                left: left.clone(),
+                right: right.clone(),
+                segments: alignment_vec.len() - 1,
+                sv_type,
+                orientation,
+            });
+        }
+    } else if let Some(clip_min) = opts.clip_min.filter(|_| !opts.split_only) {
+        // the clipped bases did not align, the clip is the only breakpoint evidence.
+        let (left, right) = soft_clip_sides(record);
+        for (side, len) in [(ClipSide::Left, left), (ClipSide::Right, right)] {
+            if len >= clip_min {
+                out.push_back(SvSignal::Clip {
+                    read: read.clone(),
+                    chrom: record.contig().to_string(),
+                    start: record.pos(),
+                    end: record.cigar().end_pos(),
+                    side,
+                    len,
+                });
+            }
+        }
+    }
+
+    // Extract Alignment event if split_only option is disabled.
+    if !opts.split_only {
+        let events = extract_alignment_events(record, opts);
+        let ins_seqs = if opts.ins_seq {
+            cigar_insertion_seqs(record, opts.indel_min)
+        } else {
+            vec![]
+        };
+        let merged = if opts.not_merge {
+            events
+        } else {
+            merge_alignment_events(&events, opts.merge_min)
+        };
+        for event in merged {
+            out.push_back(match event.svtype {
+                AlignEventType::Del => SvSignal::CigarDel {
+                    read: read.clone(),
+                    len: event.rstart - event.lend,
+                    event,
+                },
+                AlignEventType::Ins => SvSignal::CigarIns {
+                    read: read.clone(),
+                    len: event.rend - event.rstart,
+                    seq: ins_seqs
+                        .iter()
+                        .find(|(pos, _)| *pos == event.lend)
+                        .map(|(_, seq)| seq.clone()),
+                    te: None,
+                    event,
+                },
+            });
+        }
+    }
+
+    if let Some(max_insert) = opts.max_insert {
+        if let Some(x) = discordant_pair(record, header, opts, &read, max_insert) {
+            out.push_back(x);
+        }
+    }
+
+    if let Some(library) = &opts.te_library {
+        for x in out.range_mut(first..) {
+            if let SvSignal::CigarIns {
+                seq: Some(seq), te, ..
+            }
+            | SvSignal::ClipInsOneSegment {
+                seq: Some(seq), te, ..
+            }
+            | SvSignal::ClipInsTwoSegment {
+                seq: Some(seq), te, ..
+            } = x
+            {
+                *te = library.classify(seq);
+            }
+        }
+    }
+    for x in out.range_mut(first..) {
+        x.rename_contigs(&opts.contig_names);
+    }
+    Ok(())
+}
+
+/// # Large insertions from the clips of the alignments
+///
+/// Two alignments:
+///
+/// 1. Only primary and one supplimentary alignment
+/// 2. Must have overlap
+/// 3. Both alignment should have a soft-clip more than `ins_clip_min`.
+/// 4. Both alignment have same chormosome
+///
+/// One alignment(e.g. a insertion larger than average length of long-read):
+///
+/// 1. Only primary alignment
+/// 2. Alignment should have a soft-clip more than `ins_clip_min`.
+///
+/// `read_seq` is the whole read in read orientation, used to capture the
+/// inserted bases.
+fn extract_clip_insertions(
+    alignment_vec: &[SplitReadEvent],
+    opts: &ExtractorOptions,
+    read: &ReadInfo,
+    read_seq: Option<&[u8]>,
+    out: &mut VecDeque<SvSignal>,
+) {
+    let is_large_clip = |x: &SplitReadEvent| x.clip_len() > opts.ins_clip_min;
+
+    if alignment_vec.len() == 2 {
+        let a = &alignment_vec[0];
+        let b = &alignment_vec[1];
+        if !is_large_clip(a) {
+            return;
+        }
+        if a.chrom == b.chrom {
+            // if overlap > max_pct_overlap. default value of max_pct_overlap is 0 ,means each overlap pass the examination.
+            if a.strand == b.strand
+                && overlap(&a.start, &a.end, &b.start, &b.end, opts.max_pct_overlap)
+                && is_large_clip(b)
+            {
+                // make sure the insertion presents in the middile of two segments
+                let mut pos_list = [a.start, a.end, b.start, b.end];
+                pos_list.sort();
+                // the read between the two segments.
+                let seq = read_seq.and_then(|x| {
+                    let (a_from, a_to) = segment_query(x, a)?;
+                    let (b_from, b_to) = segment_query(x, b)?;
+                    let (from, to) = if a_from <= b_from {
+                        (a_to, b_from)
+                    } else {
+                        (b_to, a_from)
+                    };
+                    oriented_seq(x, a.strand, from, to)
+                });
+
+                for idx in [1, 2] {
+                    out.push_back(SvSignal::ClipInsTwoSegment {
+                        read: read.clone(),
+                        event: AlignmentEvent {
+                            lchrom: a.chrom.clone(),
+                            lstart: pos_list[0],
+                            lend: pos_list[idx],
+                            lstrand: a.strand,
+                            rchrom: b.chrom.clone(),
+                            rstart: pos_list[idx],
+                            rend: pos_list[idx],
+                            rstrand: b.strand,
+                            events_num: 1,
+                            svtype: AlignEventType::Ins,
+                        },
+                        clip_len: [a.clip_len(), b.clip_len()],
+                        seq: seq.clone(),
+                        te: None,
+                    });
+                }
+            }
+        } else {
+            out.push_back(SvSignal::ClipInsOneSegment {
+                read: read.clone(),
+                event: AlignmentEvent::from_segment_end(a),
+                clip_len: a.clip_len(),
+                segments: 2,
+                seq: read_seq.and_then(|x| segment_end_seq(x, a)),
+                te: None,
+            });
+        }
+    } else if alignment_vec.len() == 1 && is_large_clip(&alignment_vec[0]) {
+        out.push_back(SvSignal::ClipInsOneSegment {
+            read: read.clone(),
+            event: AlignmentEvent::from_segment_end(&alignment_vec[0]),
+            clip_len: alignment_vec[0].clip_len(),
+            segments: 1,
+            seq: read_seq.and_then(|x| segment_end_seq(x, &alignment_vec[0])),
+            te: None,
+        });
+    }
+}
+
+/// # Discordant pair of a short-read record
+///
+/// The pair is reported from the mate with the smaller position(from read 1
+/// when both start at the same position), so each pair is reported once,
+/// or from the other mate when that one fails the MapQ or flag filters, see
+/// [`ExtractorOptions::is_mate_selected`]. A pair whose reporting mate is
+/// outside of the regions is not reported. The alignment of the mate comes from the `MC` tag, or is assumed to be as
+/// long as this read without it.
+fn discordant_pair(
+    record: &Record,
+    header: &HeaderView,
+    opts: &ExtractorOptions,
+    read: &ReadInfo,
+    max_insert: i64,
+) -> Option<SvSignal> {
+    if !record.is_paired()
+        || record.is_unmapped()
+        || record.is_mate_unmapped()
+        || record.is_secondary()
+        || record.is_supplementary()
+        || record.mtid() < 0
+        || record.mtid() as u32 >= header.target_count()
+    {
+        return None;
+    }
+    let cigar = record.cigar().to_string();
+    let this = SplitReadEvent::new(
+        record.contig(),
+        &record.pos(),
+        parse_cigar(&cigar),
+        &read.strand,
+        &record.mapq(),
+        &cigar,
+    );
+    let mate_cigar = match record.aux(b"MC") {
+        Ok(Aux::String(x)) if is_valid_cigar(x) => x.to_string(),
+        _ => format!("{}M", record.seq_len()),
+    };
+    let mate_mapq = match record.aux(b"MQ") {
+        Ok(Aux::U8(x)) => x,
+        _ => 0,
+    };
+    let mate = SplitReadEvent::new(
+        &String::from_utf8_lossy(header.tid2name(record.mtid() as u32)),
+        &record.mpos(),
+        parse_cigar(&mate_cigar),
+        &if record.is_mate_reverse() { -1 } else { 1 },
+        &mate_mapq,
+        &mate_cigar,
+    );
+    let this_first = match alignment_pos_cmp(&this, &mate) {
+        Ordering::Less => true,
+        Ordering::Equal => record.is_first_in_template(),
+        Ordering::Greater => false,
+    };
+    if !this_first && opts.is_mate_selected(record) {
+        return None;
+    }
+    let (left, right) = if this_first {
+        (this, mate)
+    } else {
+        (mate, this)
+    };
+
+    let ori = |x: &SplitReadEvent| if x.strand < 0 { '-' } else { '+' };
+    let orientation = [ori(&left), ori(&right)];
+    let distance = (left.chrom == right.chrom).then(|| left.end.max(right.end) - left.start);
+    let sv_type = match (distance, orientation) {
+        (None, _) => SplitType::Tra,
+        (_, ['+', '+']) | (_, ['-', '-']) => SplitType::Inv,
+        // mates overlapping each other are a short insert, not a duplication.
+        (_, ['-', '+']) if right.start >= left.end => SplitType::Dup,
+        (Some(x), ['+', '-']) if x > max_insert => SplitType::Del,
+        _ => return None,
+    };
+    if !opts.split_types.contains(&sv_type) {
+        return None;
+    }
+    Some(SvSignal::Discordant {
+        read: read.clone(),
+        left,
+        right,
+        sv_type,
+        orientation,
+        distance,
+    })
+}
+
+/// Soft clips before the first and after the last aligned base, hard clips outside of them are skipped.
+fn soft_clip_sides(record: &Record) -> (u32, u32) {
+    let cigar = record.cigar();
+    let mut ops = cigar.iter().filter(|x| !matches!(x, Cigar::HardClip(_)));
+    let soft = |x: Option<&Cigar>| match x {
+        Some(Cigar::SoftClip(n)) => *n,
+        _ => 0,
+    };
+    let left = soft(ops.next());
+    let right = soft(ops.next_back());
+    (left, right)
+}
+
+/// Aligned part `[from, to)` of a segment in the read, on the forward strand
+/// of the reference. `None` if the CIGAR does not cover the whole read.
+fn segment_query(read_seq: &[u8], x: &SplitReadEvent) -> Option<(i64, i64)> {
+    let (leading, trailing) = clip_sides(&x.raw_cigar);
+    let len = read_seq.len() as i64;
+    if leading + (x.query_end - x.query_start) + trailing != len {
+        return None;
+    }
+    Some((leading, len - trailing))
+}
+
+/// The bases `[from, to)` of the read on the forward strand of a segment on `strand`.
+fn oriented_seq(read_seq: &[u8], strand: i32, from: i64, to: i64) -> Option<String> {
+    if from >= to || from < 0 || to > read_seq.len() as i64 {
+        return None;
+    }
+    let seq = if strand < 0 {
+        reverse_complement(read_seq)
+    } else {
+        read_seq.to_vec()
+    };
+    Some(String::from_utf8_lossy(&seq[from as usize..to as usize]).into_owned())
+}
+
+/// The clip after the end of a segment, where `AlignmentEvent::from_segment_end` puts the insertion.
+fn segment_end_seq(read_seq: &[u8], x: &SplitReadEvent) -> Option<String> {
+    let (_, to) = segment_query(read_seq, x)?;
+    oriented_seq(read_seq, x.strand, to, read_seq.len() as i64)
+}
+
+/// # Inserted bases of the CIGAR insertions larger than `indel_min`
+///
+/// Each sequence is given with the reference position of the insertion, the
+/// `lend` of its event. An insertion beyond the SEQ of the record, e.g. SEQ
+/// `*`, has no sequence.
+fn cigar_insertion_seqs(record: &Record, indel_min: u32) -> Vec<(i64, String)> {
+    let seq = record.seq();
+    let seq_len = record.seq_len();
+    let mut ref_pos = record.pos();
+    let mut query_pos = 0usize;
+    let mut seqs = vec![];
+    for x in record.cigar().iter() {
+        match *x {
+            Cigar::Ins(n) => {
+                if n >= indel_min && query_pos + n as usize <= seq_len {
+                    let bases = (query_pos..query_pos + n as usize)
+                        .map(|i| seq[i] as char)
+                        .collect();
+                    seqs.push((ref_pos, bases));
+                }
+                query_pos += n as usize;
+            }
+            // same reference consume as `extract_alignment_events`.
+            Cigar::Match(n) | Cigar::Equal(n) => {
+                ref_pos += n as i64;
+                query_pos += n as usize;
+            }
+            Cigar::Diff(n) | Cigar::SoftClip(n) => query_pos += n as usize,
+            Cigar::Del(n) | Cigar::RefSkip(n) => ref_pos += n as i64,
+            _ => {}
+        }
+    }
+    seqs
+}
+
+/// # Deletions and insertions larger than `indel_min` in the CIGAR of a record
+pub fn extract_alignment_events(record: &Record, opts: &ExtractorOptions) -> Vec<AlignmentEvent> {
+    let contig_name = record.contig();
+    let pos = record.pos();
+    let strand = match record.strand() {
+        ReqStrand::Forward => 1,
+        ReqStrand::Reverse => -1,
+    };
+    let cigar = record.cigar();
+
+    let total_consume: i64 = cigar
+        .iter()
+        .map(|x| match *x {
+            Cigar::Del(n) | Cigar::Match(n) | Cigar::RefSkip(n) | Cigar::Equal(n) => n as i64,
+            _ => 0,
+        })
+        .sum();
+
+    let mut alignments_event_vec: Vec<AlignmentEvent> = vec![];
+    let mut left_consume = 0i64;
+    let mut right_consume = total_consume;
+    for x in cigar.iter() {
+        match *x {
+            Cigar::Del(n) => {
+                let n = n as i64;
+                right_consume -= n;
+                if n >= opts.indel_min as i64 {
+                    // report one event
+                    alignments_event_vec.push(AlignmentEvent::new(
+                        contig_name,
+                        &left_consume,
+                        &right_consume,
+                        &n,
+                        &pos,
+                        &strand,
+                        Some(AlignEventType::Del),
+                    ));
+                }
+                left_consume += n;
+            }
+            // do not recaculate the comsume, beacuse the INS does not take account for the reference.
+            Cigar::Ins(n) if n >= opts.indel_min => {
+                alignments_event_vec.push(AlignmentEvent::new(
+                    contig_name,
+                    &left_consume,
+                    &(n as i64), // for Insetions derived from CIGAR value, encode the length in right region.
+                    &0,
+                    &pos,
+                    &strand,
+                    Some(AlignEventType::Ins),
+                ));
+            }
+            Cigar::Match(n) | Cigar::RefSkip(n) | Cigar::Equal(n) => {
+                left_consume += n as i64;
+                right_consume -= n as i64;
+            }
+            _ => {}
+        }
+    }
+    alignments_event_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::SignalType;
+    use rust_htslib::bam::{header::HeaderRecord, Header};
+    use std::rc::Rc;
+
+    fn header() -> HeaderView {
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr1")
+                .push_tag(b"LN", 100_000),
+        );
+        HeaderView::from_header(&header)
+    }
+
+    fn record(header: &HeaderView, line: &str) -> Record {
+        let mut record = Record::from_sam(header, line.replace(' ', "\t").as_bytes()).unwrap();
+        record.set_header(Rc::new(header.clone()));
+        record
+    }
+
+    /// Records given by a test, followed by `error` if any.
+    struct Records {
+        header: HeaderView,
+        records: VecDeque<Record>,
+        error: Option<rust_htslib::errors::Error>,
+    }
+
+    impl RecordSource for Records {
+        fn read_record(&mut self, record: &mut Record) -> Option<HtsResult<()>> {
+            match self.records.pop_front() {
+                Some(x) => {
+                    *record = x;
+                    Some(Ok(()))
+                }
+                None => self.error.take().map(Err),
+            }
+        }
+
+        fn header_view(&self) -> &HeaderView {
+            &self.header
+        }
+    }
+
+    fn extractor(lines: &[&str], opts: ExtractorOptions) -> SignalExtractor<Records> {
+        let header = header();
+        let records = lines.iter().map(|x| record(&header, x)).collect();
+        SignalExtractor::new(
+            Records {
+                header,
+                records,
+                error: None,
+            },
+            opts,
+        )
+    }
+
+    fn types(x: SignalExtractor<Records>) -> Vec<SignalType> {
+        x.map(|x| x.unwrap().signal_type()).collect()
+    }
+
+    #[test]
+    fn streams_the_signals_in_the_order_of_the_records() {
+        let x = extractor(
+            &[
+                "del 0 chr1 1001 60 100M200D100M * 0 0 * *",
+                "low 0 chr1 1001 0 100M200D100M * 0 0 * *",
+                "split 0 chr1 2001 60 100M100S * 0 0 * * SA:Z:chr1,8001,+,100S100M,60,0;",
+                "ins 0 chr1 3001 60 100M80I100M * 0 0 * *",
+            ],
+            ExtractorOptions::default(),
+        );
+        assert_eq!(
+            types(x),
+            [
+                SignalType::CigarDel,
+                SignalType::Split,
+                SignalType::CigarIns
+            ]
+        );
+        let x = extractor(
+            &["split 0 chr1 2001 60 100M100S * 0 0 * * SA:Z:chr1,8001,+,100S100M,60,0;"],
+            ExtractorOptions {
+                split_types: vec![SplitType::Inv],
+                ..Default::default()
+            },
+        );
+        assert!(types(x).is_empty());
+    }
+
+    #[test]
+    fn adjacent_deletions_are_merged_unless_not_merge() {
+        let line = "del 0 chr1 1001 60 100M60D3M60D100M * 0 0 * *";
+        let lens = |opts| {
+            extractor(&[line], opts)
+                .map(|x| match x.unwrap() {
+                    SvSignal::CigarDel { len, .. } => len,
+                    x => panic!("not a deletion: {:?}", x),
+                })
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(lens(ExtractorOptions::default()), [123]);
+        let not_merge = ExtractorOptions {
+            not_merge: true,
+            ..Default::default()
+        };
+        assert_eq!(lens(not_merge), [60, 60]);
+    }
+
+    #[test]
+    fn invalid_records_follow_the_error_policy() {
+        let lines = [
+            "bad 0 chr1 1001 60 100M100S * 0 0 * * SA:Z:chr1,8oo1,+,100S100M,60,0;",
+            "del 0 chr1 1001 60 100M200D100M * 0 0 * *",
+        ];
+        let mut x = extractor(&lines, ExtractorOptions::default());
+        assert!(matches!(x.next(), Some(Err(ExcordError::Record { .. }))));
+        assert!(x.next().is_none());
+        let mut x = extractor(
+            &lines,
+            ExtractorOptions {
+                on_error: OnError::Skip,
+                ..Default::default()
+            },
+        );
+        assert_eq!(x.by_ref().filter_map(|x| x.ok()).count(), 1);
+        assert_eq!(x.skipped(), 1);
+    }
+
+    #[test]
+    fn read_errors_tell_the_last_record() {
+        let mut x = extractor(
+            &["del 0 chr1 1001 60 100M200D100M * 0 0 * *"],
+            ExtractorOptions::default(),
+        );
+        x.reader.error = Some(rust_htslib::errors::Error::BamTruncatedRecord);
+        assert!(x.next().unwrap().is_ok());
+        match x.next() {
+            Some(Err(ExcordError::Read { after, .. })) => {
+                assert_eq!(after, Some(("chr1".to_string(), 1000)))
+            }
+            x => panic!("not a read error: {:?}", x),
+        }
+        assert!(x.next().is_none());
+    }
+
+    fn pair_opts() -> ExtractorOptions {
+        ExtractorOptions {
+            max_insert: Some(1000),
+            ..Default::default()
+        }
+    }
+
+    fn discordant(header: &HeaderView, line: &str) -> Option<SvSignal> {
+        let mut out = VecDeque::new();
+        let read_groups = ReadGroups::from_header(header);
+        extract_record(
+            &record(header, line),
+            header,
+            &read_groups,
+            &pair_opts(),
+            &mut out,
+        )
+        .unwrap();
+        out.into_iter()
+            .find(|x| x.signal_type() == SignalType::Discordant)
+    }
+
+    #[test]
+    fn mate_filters_come_from_mq_and_swapped_flags() {
+        let header = header();
+        let opts = ExtractorOptions {
+            mapq: 20,
+            exclude_flag: 0x10,
+            ..Default::default()
+        };
+        let mate = |flags: u16, mq: &str| {
+            record(
+                &header,
+                &format!("r {} chr1 1000 60 10M = 9000 8010 * * {}", flags, mq),
+            )
+        };
+        assert!(opts.is_mate_selected(&mate(65, "MQ:i:60")));
+        assert!(opts.is_mate_selected(&mate(65, "XX:i:0")));
+        assert!(!opts.is_mate_selected(&mate(65, "MQ:i:5")));
+        // 0x20: the mate is reverse, excluded by 0x10.
+        assert!(!opts.is_mate_selected(&mate(97, "MQ:i:60")));
+        assert!(opts.is_mate_selected(&mate(81, "MQ:i:60")));
+    }
+
+    #[test]
+    fn discordant_pair_is_reported_once() {
+        let header = header();
+        let left = "d 97 chr1 1000 60 10M = 9000 8010 * * MC:Z:10M MQ:i:60";
+        let right = "d 145 chr1 9000 60 10M = 1000 -8010 * * MC:Z:10M MQ:i:60";
+        let Some(SvSignal::Discordant {
+            left: l,
+            right: r,
+            sv_type,
+            ..
+        }) = discordant(&header, left)
+        else {
+            panic!("no discordant pair");
+        };
+        assert_eq!((l.start, r.start, sv_type), (999, 8999, SplitType::Del));
+        assert!(discordant(&header, right).is_none());
+    }
+
+    #[test]
+    fn discordant_pair_is_reported_by_the_right_mate_when_the_left_one_fails() {
+        let header = header();
+        let right = "d 145 chr1 9000 60 10M = 1000 -8010 * * MC:Z:10M MQ:i:0";
+        let Some(SvSignal::Discordant {
+            read, left, right, ..
+        }) = discordant(&header, right)
+        else {
+            panic!("no discordant pair");
+        };
+        assert_eq!(read.flags, 145);
+        assert_eq!((left.start, right.start), (999, 8999));
+    }
+}
@@ -0,0 +1,71 @@
+use rust_htslib::bam::Record;
+
+/// # Insert size distribution of a paired-end library
+///
+/// Estimated from the outer distance(`TLEN`) of proper pairs. The median and
+/// the MAD are used instead of the mean and the standard deviation, so a few
+/// chimeric pairs do not widen the distribution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InsertSize {
+    pub median: f64,
+    /// MAD scaled to the standard deviation of a normal distribution(`1.4826 * MAD`)
+    pub sd: f64,
+    /// Number of pairs used for the estimate
+    pub pairs: usize,
+}
+
+impl InsertSize {
+    /// Return `true` if the record is counted, once per proper pair from the
+    /// mate with a positive `TLEN`. Secondary and supplementary records are ignored.
+    pub fn is_sampled(record: &Record) -> bool {
+        record.is_proper_pair()
+            && !record.is_secondary()
+            && !record.is_supplementary()
+            && record.insert_size() > 0
+    }
+
+    /// Estimate from the first `n` proper pairs of the records, `None` if there is no proper pair.
+    ///
+    /// Read the records ahead with
+    /// [`Prefetched`](crate::extractor::Prefetched) to estimate from the start
+    /// of the input and still extract all of it.
+    pub fn estimate<'a>(
+        records: impl IntoIterator<Item = &'a Record>,
+        n: usize,
+    ) -> Option<InsertSize> {
+        let mut sizes: Vec<i64> = records
+            .into_iter()
+            .filter(|x| InsertSize::is_sampled(x))
+            .map(|x| x.insert_size())
+            .take(n)
+            .collect();
+        if sizes.is_empty() {
+            return None;
+        }
+        let mid = median(&mut sizes);
+        let mut deviations: Vec<i64> = sizes
+            .iter()
+            .map(|x| (*x as f64 - mid).abs().round() as i64)
+            .collect();
+        Some(InsertSize {
+            median: mid,
+            sd: 1.4826 * median(&mut deviations),
+            pairs: sizes.len(),
+        })
+    }
+
+    /// Largest outer distance of a concordant pair, `n_sd` standard deviations above the median.
+    pub fn max_insert(&self, n_sd: f64) -> i64 {
+        (self.median + n_sd * self.sd).ceil() as i64
+    }
+}
+
+fn median(x: &mut [i64]) -> f64 {
+    x.sort_unstable();
+    let mid = x.len() / 2;
+    if x.len().is_multiple_of(2) {
+        (x[mid - 1] + x[mid]) as f64 / 2.0
+    } else {
+        x[mid] as f64
+    }
+}
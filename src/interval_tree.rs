@@ -0,0 +1,196 @@
+/// # Interval with a value
+///
+/// 0-based and half-open, an empty interval is treated as the single base at `start`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interval<T> {
+    pub start: i64,
+    pub end: i64,
+    pub value: T,
+}
+
+/// # Static interval tree
+///
+/// The intervals are sorted by start and stored as an implicit balanced
+/// binary tree (the layout of cgranges): the node at index `i` of level `k`
+/// has its children at `i ± 2^(k-1)`, and every node holds the largest end
+/// of its subtree. Built once, queried in `O(log n + hits)`.
+#[derive(Debug, Clone)]
+pub struct IntervalTree<T> {
+    intervals: Vec<Interval<T>>,
+    /// largest end of the subtree of each node
+    max_end: Vec<i64>,
+    /// level of the root
+    root_level: u32,
+}
+
+impl<T> IntervalTree<T> {
+    pub fn new(mut intervals: Vec<Interval<T>>) -> IntervalTree<T> {
+        for x in intervals.iter_mut() {
+            x.end = x.end.max(x.start + 1);
+        }
+        intervals.sort_by_key(|x| (x.start, x.end));
+        let n = intervals.len();
+        let mut max_end: Vec<i64> = intervals.iter().map(|x| x.end).collect();
+        if n == 0 {
+            return IntervalTree {
+                intervals,
+                max_end,
+                root_level: 0,
+            };
+        }
+
+        // the leaves are the even indices, each level up fills the nodes between them.
+        let mut last_i = n - 1 - (n - 1) % 2;
+        let mut last = max_end[last_i];
+        let mut k = 1;
+        while 1usize << k <= n {
+            let x = 1usize << (k - 1);
+            let i0 = (x << 1) - 1;
+            let step = x << 2;
+            let mut i = i0;
+            while i < n {
+                let left = max_end[i - x];
+                let right = if i + x < n { max_end[i + x] } else { last };
+                max_end[i] = max_end[i].max(left).max(right);
+                i += step;
+            }
+            last_i = if (last_i >> k) & 1 == 1 {
+                last_i - x
+            } else {
+                last_i + x
+            };
+            if last_i < n {
+                last = last.max(max_end[last_i]);
+            }
+            k += 1;
+        }
+        IntervalTree {
+            intervals,
+            max_end,
+            root_level: k - 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Intervals overlapping `[start, end)`, sorted by start.
+    pub fn find(&self, start: i64, end: i64) -> Vec<&Interval<T>> {
+        let end = end.max(start + 1);
+        let n = self.intervals.len();
+        let mut hits = vec![];
+        if n == 0 {
+            return hits;
+        }
+        // (node, level, left subtree done)
+        let mut stack = vec![((1usize << self.root_level) - 1, self.root_level, false)];
+        while let Some((x, k, done)) = stack.pop() {
+            if k <= 3 {
+                // small subtree, scanned in order.
+                let i0 = x >> k << k;
+                let i1 = (i0 + (1 << (k + 1)) - 1).min(n);
+                for i in (i0..i1).take_while(|i| self.intervals[*i].start < end) {
+                    if start < self.intervals[i].end {
+                        hits.push(&self.intervals[i]);
+                    }
+                }
+            } else if !done {
+                let y = x - (1 << (k - 1));
+                stack.push((x, k, true));
+                if y >= n || self.max_end[y] > start {
+                    stack.push((y, k - 1, false));
+                }
+            } else if x < n && self.intervals[x].start < end {
+                if start < self.intervals[x].end {
+                    hits.push(&self.intervals[x]);
+                }
+                stack.push((x + (1 << (k - 1)), k - 1, false));
+            }
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(intervals: &[(i64, i64)]) -> IntervalTree<usize> {
+        IntervalTree::new(
+            intervals
+                .iter()
+                .enumerate()
+                .map(|(i, (start, end))| Interval {
+                    start: *start,
+                    end: *end,
+                    value: i,
+                })
+                .collect(),
+        )
+    }
+
+    fn found(tree: &IntervalTree<usize>, start: i64, end: i64) -> Vec<usize> {
+        tree.find(start, end).iter().map(|x| x.value).collect()
+    }
+
+    #[test]
+    fn finds_overlaps_sorted_by_start() {
+        let t = tree(&[(100, 200), (0, 50), (150, 160), (300, 400)]);
+        assert_eq!(t.len(), 4);
+        assert_eq!(found(&t, 140, 170), vec![0, 2]);
+        assert_eq!(found(&t, 200, 300), Vec::<usize>::new());
+        assert_eq!(found(&t, 0, 1000), vec![1, 0, 2, 3]);
+        // empty intervals and queries are the single base at their start.
+        let t = tree(&[(10, 10)]);
+        assert_eq!(found(&t, 10, 10), vec![0]);
+        assert_eq!(found(&t, 11, 20), Vec::<usize>::new());
+        assert!(tree(&[]).find(0, 100).is_empty());
+    }
+
+    #[test]
+    fn matches_a_linear_scan() {
+        // deterministic pseudo-random intervals, long ones mixed with short ones.
+        let mut seed = 42u64;
+        let mut next = |n: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((seed >> 33) % n) as i64
+        };
+        for n in [1, 2, 3, 7, 8, 9, 16, 17, 100, 257] {
+            let intervals: Vec<(i64, i64)> = (0..n)
+                .map(|_| {
+                    let start = next(10_000);
+                    let len = if next(10) == 0 {
+                        next(5_000)
+                    } else {
+                        next(100)
+                    };
+                    (start, start + len)
+                })
+                .collect();
+            let t = tree(&intervals);
+            for _ in 0..50 {
+                let start = next(11_000) - 500;
+                let end = start + next(300);
+                let mut expected: Vec<usize> = (0..intervals.len())
+                    .filter(|i| {
+                        let (s, e) = intervals[*i];
+                        s < end.max(start + 1) && start < e.max(s + 1)
+                    })
+                    .collect();
+                let mut hits = found(&t, start, end);
+                assert!(t
+                    .find(start, end)
+                    .windows(2)
+                    .all(|x| x[0].start <= x[1].start));
+                expected.sort();
+                hits.sort();
+                assert_eq!(hits, expected, "n = {}, query {}-{}", n, start, end);
+            }
+        }
+    }
+}
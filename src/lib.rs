@@ -0,0 +1,59 @@
+//! # excord-lr
+//!
+//! Extract Structural Variation signals from Long-Read BAMs.
+//!
+//! The extraction logic is exposed through [`SignalExtractor`], which wraps any
+//! `rust_htslib::bam::Read` and yields one [`SvSignal`] per signal, or an
+//! [`ExcordError`] if the input can not be read:
+//!
+//! ```no_run
+//! use excord_lr::{ExtractorOptions, SignalExtractor};
+//! use rust_htslib::bam;
+//!
+//! let reader = bam::Reader::from_path("sample.bam").unwrap();
+//! for x in SignalExtractor::new(reader, ExtractorOptions::default()) {
+//!     let x = x.unwrap();
+//!     println!("{}\t{}", x.signal_type(), x.read().qname);
+//! }
+//! ```
+pub mod aligments_event;
+pub mod bed_record;
+pub mod cluster;
+pub mod contig_naming;
+pub mod dedup;
+pub mod error;
+pub mod extractor;
+pub mod insert_size;
+pub mod interval_tree;
+pub mod normal;
+pub mod output;
+pub mod parallel;
+pub mod query;
+pub mod read_group;
+pub mod ref_support;
+pub mod reference;
+pub mod region;
+pub mod signal;
+pub mod sort;
+pub mod split_read_event;
+pub mod te;
+pub mod utils;
+pub mod vcf;
+pub mod writer;
+
+pub use aligments_event::{AlignEventType, AlignmentEvent};
+pub use contig_naming::{ContigNames, ContigNaming};
+pub use dedup::SplitDedup;
+pub use error::{ExcordError, OnError};
+pub use extractor::{ExtractorOptions, Prefetched, RecordSource, SignalExtractor};
+pub use insert_size::InsertSize;
+pub use normal::NormalSignals;
+pub use read_group::{ReadGroups, SplitBy};
+pub use ref_support::{AlleleCounts, RefSupport, RefSupportOptions};
+pub use reference::{InputFormat, RefPath, SqLine};
+pub use region::{Region, RegionReader};
+pub use signal::{ClipSide, ReadInfo, SignalType, SplitType, SvSignal};
+pub use split_read_event::SplitReadEvent;
+pub use te::{TeHit, TeLibrary};
+pub use vcf::VcfWriter;
+pub use writer::{Annotation, BedWriter, InsFastaWriter, SignalWriter};
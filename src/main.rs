@@ -1,30 +1,231 @@
-use bio_types::{genome::AbstractInterval, strand::ReqStrand};
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use excord_lr::{
+    bed_record::{open_bed, BedColumns, SignalRecord},
+    cluster::{ClusterOptions, Clusterer, SvCall},
+    output::{IndexFormat, IndexPreset, Output, OutputOptions},
+    parallel::extract_parallel,
+    query::{SignalIndex, SvFormat, SvQuery},
+    reference::{check_contigs, fasta_contigs, header_contigs},
+    region::{read_regions_bed, resolve_regions, to_chunks, whole_genome},
+    utils::*,
+    AlleleCounts, Annotation, BedWriter, ContigNames, ContigNaming, ExcordError, ExtractorOptions,
+    InputFormat, InsFastaWriter, InsertSize, NormalSignals, OnError, Prefetched, ReadGroups,
+    RecordSource, RefPath, RefSupport, RefSupportOptions, Region, RegionReader, SignalExtractor,
+    SignalWriter, SplitBy, SplitDedup, SplitType, SvSignal, TeLibrary, VcfWriter,
+};
 use rust_htslib::{
     bam,
-    bam::{
-        // ext::BamRecordExtensions,
-        record::{Aux, Cigar},
-        Read,
-        Record
-    },
+    bam::{HeaderView, Read},
 };
 use std::{
-    cmp::Ordering,
     collections::HashMap,
     fs::File,
-    io::{BufWriter, Write},
-    ops::BitAnd,
-    path::PathBuf,
+    io::{self, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
     process::exit,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
 };
-mod split_read_event;
-use split_read_event::SplitReadEvent;
-mod utils;
-use utils::*;
-mod aligments_event;
-use aligments_event::*;
-use std::str;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// BEDPE-like records read by STIX
+    Bed,
+    /// VCF 4.2, split reads as BND records
+    Vcf,
+}
+
+/// Writer of the chosen format over one output file.
+enum FormatWriter {
+    Bed(BedWriter<Output>),
+    Vcf(VcfWriter<Output>),
+}
+
+impl FormatWriter {
+    fn create(
+        path: &Path,
+        cli: &Cli,
+        header: &HeaderView,
+        out_opts: &OutputOptions,
+        contigs: Vec<String>,
+        names: &ContigNames,
+    ) -> Result<FormatWriter, ExcordError> {
+        let write_error = |e| ExcordError::Write {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let out = Output::create(path, out_opts, contigs).map_err(write_error)?;
+        Ok(match cli.format {
+            Format::Bed => FormatWriter::Bed(
+                BedWriter::new(out, cli.verbose)
+                    .with_sample(cli.sample_column)
+                    .with_annotation(cli.ref_support, cli.normal_bam.is_some()),
+            ),
+            Format::Vcf => FormatWriter::Vcf(
+                VcfWriter::new(out, header, names)
+                    .map_err(write_error)?
+                    .with_sample(cli.sample_column),
+            ),
+        })
+    }
+
+    fn write_signal(&mut self, x: &SvSignal, annotation: &Annotation) -> io::Result<()> {
+        match self {
+            FormatWriter::Bed(w) => w.write_annotated(x, annotation),
+            FormatWriter::Vcf(w) => w.write_annotated(x, annotation),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            FormatWriter::Bed(mut w) => {
+                w.finish()?;
+                w.into_inner().finish()
+            }
+            FormatWriter::Vcf(mut w) => {
+                w.finish()?;
+                w.into_inner().finish()
+            }
+        }
+    }
+}
+
+/// Output of a run, one file or one per group.
+enum Outputs {
+    Single(FormatWriter),
+    Groups(GroupOutput),
+}
+
+/// One file of a split output.
+struct GroupFile {
+    id: String,
+    sample: Option<String>,
+    path: PathBuf,
+    writer: FormatWriter,
+    signals: u64,
+}
+
+/// # Output split by sample or read group
+///
+/// A file is created for each sample/read group of the header, and one for
+/// the reads without a known sample/read group(`unassigned`) when needed.
+/// The manifest lists the files.
+struct GroupOutput {
+    by: SplitBy,
+    files: Vec<GroupFile>,
+    index: HashMap<String, usize>,
+}
+
+const UNASSIGNED: &str = "unassigned";
+
+impl GroupOutput {
+    fn create(
+        by: SplitBy,
+        read_groups: &ReadGroups,
+        mut open: impl FnMut(&str) -> Result<(PathBuf, FormatWriter), ExcordError>,
+    ) -> Result<GroupOutput, ExcordError> {
+        let mut groups: Vec<(String, Option<String>)> = vec![];
+        for id in read_groups.ids() {
+            let sample = read_groups.sample(id).map(|x| x.to_string());
+            match by {
+                SplitBy::ReadGroup => groups.push((id.clone(), sample)),
+                SplitBy::Sample => {
+                    if let Some(sample) = sample.filter(|x| !groups.iter().any(|g| &g.0 == x)) {
+                        groups.push((sample.clone(), Some(sample)));
+                    }
+                }
+            }
+        }
+        let mut output = GroupOutput {
+            by,
+            files: vec![],
+            index: HashMap::new(),
+        };
+        for (id, sample) in groups {
+            output.add(id, sample, &mut open)?;
+        }
+        Ok(output)
+    }
+
+    fn add(
+        &mut self,
+        id: String,
+        sample: Option<String>,
+        open: &mut impl FnMut(&str) -> Result<(PathBuf, FormatWriter), ExcordError>,
+    ) -> Result<usize, ExcordError> {
+        let (path, writer) = open(&id)?;
+        self.index.insert(id.clone(), self.files.len());
+        self.files.push(GroupFile {
+            id,
+            sample,
+            path,
+            writer,
+            signals: 0,
+        });
+        Ok(self.files.len() - 1)
+    }
+
+    fn write_signal(
+        &mut self,
+        x: &SvSignal,
+        annotation: &Annotation,
+        open: &mut impl FnMut(&str) -> Result<(PathBuf, FormatWriter), ExcordError>,
+    ) -> Result<(), ExcordError> {
+        let read = x.read();
+        let id = match self.by {
+            SplitBy::Sample => read.sample.as_deref(),
+            SplitBy::ReadGroup => read.read_group.as_deref(),
+        }
+        .unwrap_or(UNASSIGNED);
+        let i = match self.index.get(id) {
+            Some(i) => *i,
+            None => self.add(id.to_string(), read.sample.clone(), open)?,
+        };
+        let file = &mut self.files[i];
+        file.signals += 1;
+        file.writer
+            .write_signal(x, annotation)
+            .map_err(|e| ExcordError::Write {
+                path: file.path.clone(),
+                source: e,
+            })
+    }
+
+    /// Finish the files and write the manifest: id, sample, number of signals and path.
+    fn finish(self, manifest: &Path) -> Result<(), ExcordError> {
+        let manifest_error = |e| ExcordError::Write {
+            path: manifest.to_path_buf(),
+            source: e,
+        };
+        let mut f = BufWriter::new(File::create(manifest).map_err(manifest_error)?);
+        writeln!(f, "#{}\tsample\tsignals\tpath", self.by).map_err(manifest_error)?;
+        for file in self.files {
+            file.writer.finish().map_err(|e| ExcordError::Write {
+                path: file.path.clone(),
+                source: e,
+            })?;
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                file.id,
+                file.sample.as_deref().unwrap_or("."),
+                file.signals,
+                file.path.display()
+            )
+            .map_err(manifest_error)?;
+        }
+        f.flush().map_err(manifest_error)
+    }
+}
+
+/// The input BAM/CRAM, either read through or restricted to the resolved regions.
+enum Input {
+    Whole(Prefetched<bam::Reader>),
+    Indexed(bam::IndexedReader, Vec<(u32, i64, i64)>),
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "excord-LR")]
@@ -34,14 +235,34 @@ use std::str;
 Extract Structural Variation Signals from Long-Read BAMs
 Contact: Xinchang Zheng <zhengxc93@gmail.com,Xinchang.Zheng@bcm.edu>
 ", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Path to BAM file
-    #[arg(short, long)]
-    bam: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Path to reference, used for CRAM file
+    /// Path to BAM/SAM/CRAM files, can be repeated or given as a list, - for stdin
+    #[arg(short, long, num_args = 1.., required_unless_present = "bam_list")]
+    bam: Vec<PathBuf>,
+
+    /// File with one BAM/CRAM path per line, added to --bam
+    #[arg(long)]
+    bam_list: Option<PathBuf>,
+
+    /// Number of input BAM/CRAM files processed at once
+    #[arg(long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Path to reference, used for CRAM file, found from REF_CACHE/REF_PATH when not given
     #[arg(short, long)]
-    reference:Option<PathBuf>,
+    reference: Option<PathBuf>,
+
+    /// Contig names in the output(keep, strip-chr, add-chr, alias) [default: strip-chr]
+    #[arg(long)]
+    contig_naming: Option<ContigNaming>,
+
+    /// File mapping the contig names of the BAM(1st column) to the output names(2nd column), implies --contig-naming alias
+    #[arg(long)]
+    contig_alias: Option<PathBuf>,
 
     /// Minimal MapQ
     #[arg(short = 'Q', long, default_value_t = 1)]
@@ -72,29 +293,153 @@ struct Cli {
     merge_min: u32,
 
     /// Minimal length of hard-clip and soft-clip to define a large insertion signal
-    #[arg(short, long, default_value_t = 1000)]
+    #[arg(long, default_value_t = 1000)]
     ins_clip_min: u32,
 
-    /// Not merge
+    /// Do not merge adjacent CIGAR deletions
     #[arg(short, long, default_value_t = false)]
     not_merge: bool,
 
-    /// Output file name
-    #[arg(short, long)]
-    out: PathBuf,
+    /// Output file name, - for stdout, {sample} and {name} are replaced by the sample and the file name of the input
+    #[arg(short, long, required = true)]
+    out: Option<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Bed)]
+    format: Format,
+
+    /// Write a CSI instead of a TBI index for a compressed(.gz) output
+    #[arg(long, default_value_t = false)]
+    csi: bool,
+
+    /// Sort the records by contig and start
+    #[arg(long, default_value_t = false)]
+    sort: bool,
+
+    /// Memory(MB) used to buffer records before a sorted run is spilled to disk
+    #[arg(long, default_value_t = 768)]
+    sort_mem: usize,
+
+    /// Directory of the sorted runs spilled to disk [default: system temporary directory]
+    #[arg(long)]
+    tmp_dir: Option<PathBuf>,
 
     /// Only report split-read event
     #[arg(short, long, default_value_t = false)]
     split_only: bool,
 
+    /// SV types of the split-read pairs to report(DEL,DUP,INV,TRA)
+    #[arg(long, value_delimiter = ',', default_value = "DEL,DUP,INV,TRA")]
+    split_type: Vec<SplitType>,
+
     /// Percent of overlap to discard a potential false positive record[Optional]
     #[arg(short = 'p', long, default_value_t = 0.0)]
     max_pct_overlap: f64,
 
+    /// Only extract signals in this region (chr:start-end, or a whole contig whose name has ':'), can be repeated. Requires an index
+    #[arg(long)]
+    region: Vec<String>,
+
+    /// Only extract signals in the regions of this BED file. Requires an index
+    #[arg(long)]
+    regions_bed: Option<PathBuf>,
+
+    /// Number of workers extracting contigs/chunks in parallel(0 to disable). Requires an index
+    #[arg(long, default_value_t = 0)]
+    workers: usize,
+
+    /// Size of the genomic chunks processed by each worker
+    #[arg(long, default_value_t = 10_000_000)]
+    chunk_size: i64,
+
+    /// Emit the split-read chain of each read once, from the first record of the read
+    #[arg(long, default_value_t = false)]
+    dedup_split: bool,
+
+    /// Paired-end short reads: also report discordant pairs, from the leftmost mate passing the filters, not when that mate is outside the regions
+    #[arg(long, default_value_t = false)]
+    short_read: bool,
+
+    /// Number of proper pairs to estimate the insert size from(--short-read)
+    #[arg(long, default_value_t = 10_000)]
+    insert_sample: usize,
+
+    /// A forward-reverse pair is discordant this many standard deviations above the median insert size(--short-read)
+    #[arg(long, default_value_t = 5.0)]
+    insert_sd: f64,
+
+    /// Maximal insert size of a concordant pair, replaces the estimate(--short-read)
+    #[arg(long)]
+    max_insert: Option<i64>,
+
+    /// Report soft clips of at least this length of records without SA as clip signals
+    #[arg(long)]
+    clip_min: Option<u32>,
+
+    /// Attach the inserted sequence to the insertion signals(verbose and VCF output)
+    #[arg(long, default_value_t = false)]
+    ins_seq: bool,
+
+    /// Also write the inserted sequences to this FASTA file, implies --ins-seq
+    #[arg(long)]
+    ins_fasta: Option<PathBuf>,
+
+    /// FASTA of TE consensus sequences, label the inserted sequences with the best family, implies --ins-seq
+    #[arg(long)]
+    te_library: Option<PathBuf>,
+
+    /// k-mer size to match the inserted sequences against the TE library
+    #[arg(long, default_value_t = 15)]
+    te_kmer: usize,
+
+    /// Minimal identity to label an inserted sequence with a TE family
+    #[arg(long, default_value_t = 0.85)]
+    te_min_identity: f64,
+
     /// Maximal number of SA to include a record
     #[arg(short = 'k', long, default_value_t = 4)]
     max_supp_alignm: usize,
 
+    /// What to do with an invalid record, e.g. a malformed SA tag(abort, skip, warn)
+    #[arg(long, default_value = "abort")]
+    on_error: OnError,
+
+    /// Add the sample(SM of the read group) of each signal as a column(BED) or to INFO(VCF)
+    #[arg(long, default_value_t = false)]
+    sample_column: bool,
+
+    /// Add the reads spanning the breakpoints without signal(ref), the reads with a signal(alt) and the VAF. Requires an index
+    #[arg(long, default_value_t = false)]
+    ref_support: bool,
+
+    /// Minimal aligned bases on both sides of a breakpoint for a reference read(--ref-support)
+    #[arg(long, default_value_t = 20)]
+    ref_flank: i64,
+
+    /// Reads with a signal of the same type this close(bp) to a breakpoint are alternate reads(--ref-support)
+    #[arg(long, default_value_t = 50)]
+    ref_slop: i64,
+
+    /// BAM/CRAM of the normal sample, only the signals without a match in the normal are written, with the normal support as a column(BED) or in INFO(VCF)
+    #[arg(long)]
+    normal_bam: Option<PathBuf>,
+
+    /// Maximal distance(bp) between the breakpoints of a tumor signal and a matching normal signal(--normal-bam)
+    #[arg(long, default_value_t = 100)]
+    normal_tolerance: i64,
+
+    /// Minimal ratio of the smaller to the larger length of a tumor and a matching normal CIGAR deletion or insertion(--normal-bam)
+    #[arg(long, default_value_t = 0.7)]
+    normal_size_similarity: f64,
+
+    /// Write the signals supported by at most this many normal reads(--normal-bam)
+    #[arg(long, default_value_t = 0)]
+    max_normal_support: usize,
+
+    /// Write one file per sample or read group, named after -o, with a manifest(sample, read-group)
+    #[arg(long)]
+    split_by: Option<SplitBy>,
+
     /// Debug
     #[arg(short, long, default_value_t = false)]
     debug: bool,
@@ -104,668 +449,941 @@ struct Cli {
     verbose: bool,
 }
 
+/// Commands working on the output of excord-lr.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Cluster the sorted signals of a --verbose BED output into candidate SV calls
+    Cluster(ClusterCli),
+    /// Count the signals of a --verbose BED output supporting each SV of a VCF or BEDPE
+    Query(QueryCli),
+}
+
+#[derive(Args, Debug)]
+struct ClusterCli {
+    /// BED output of excord-lr written with --verbose and --sort(plain or .gz), - for stdin
+    #[arg(short, long)]
+    input: PathBuf,
+
+    /// Output file name, - for stdout. A .gz output is compressed and indexed
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Maximal distance(bp) of a breakpoint to the mean breakpoint of a cluster
+    #[arg(long, default_value_t = 500)]
+    max_distance: i64,
+
+    /// Minimal ratio of the smaller to the larger SV size in a cluster
+    #[arg(long, default_value_t = 0.7)]
+    min_size_similarity: f64,
+
+    /// Minimal number of supporting reads of a call
+    #[arg(long, default_value_t = 2)]
+    min_support: usize,
+}
+
+#[derive(Args, Debug)]
+struct QueryCli {
+    /// BED output of excord-lr written with --verbose(plain or .gz), - for stdin
+    #[arg(short, long)]
+    input: PathBuf,
+
+    /// SVs to query, VCF or BEDPE(plain or .gz)
+    #[arg(long)]
+    sv: PathBuf,
+
+    /// Format of --sv(vcf, bedpe) [default: detected from the header]
+    #[arg(long)]
+    sv_format: Option<SvFormat>,
+
+    /// Distance(bp) added on both sides of each breakpoint
+    #[arg(long, default_value_t = 500)]
+    slop: i64,
+
+    /// Output file name, - for stdout
+    #[arg(short, long)]
+    out: PathBuf,
+}
+
 fn main() {
     let cli = Cli::parse();
     if cli.debug {
-        println!("{:?}", &cli);
+        eprintln!("{:?}", &cli);
     }
-
-    let _bam = cli.bam;
-    if !_bam.is_file() {
-        println!("Ivalid BAM file path: {} ", _bam.to_str().unwrap());
-        exit(1)
+    let result = match &cli.command {
+        Some(Command::Cluster(args)) => run_cluster(args),
+        Some(Command::Query(args)) => run_query(args),
+        None => run(cli),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(e.exit_code())
     }
+}
 
+fn open_error(path: &Path) -> impl Fn(rust_htslib::errors::Error) -> ExcordError + '_ {
+    move |e| ExcordError::Open {
+        path: path.to_path_buf(),
+        msg: e.to_string(),
+    }
+}
 
+/// Records read ahead per proper pair to sample before the insert size estimate gives up.
+const RECORDS_PER_SAMPLED_PAIR: usize = 20;
+
+/// Estimate the insert size from the start of the BAM/CRAM, report it on stderr.
+/// The records read for it stay in `reader`, at most
+/// `n * RECORDS_PER_SAMPLED_PAIR` of them.
+fn estimate_max_insert<R: RecordSource>(
+    reader: &mut Prefetched<R>,
+    n: usize,
+    n_sd: f64,
+    tag: &str,
+) -> Result<i64, ExcordError> {
+    let limit = n.saturating_mul(RECORDS_PER_SAMPLED_PAIR).max(1);
+    let mut sampled = 0usize;
+    let mut seen = 0usize;
+    reader
+        .read_ahead(|x| {
+            sampled += InsertSize::is_sampled(x) as usize;
+            seen += 1;
+            sampled < n && seen < limit
+        })
+        .map_err(|source| ExcordError::Read {
+            after: None,
+            source,
+        })?;
+    if sampled < n && seen >= limit {
+        return Err(ExcordError::Usage(format!(
+            "Only {} proper pairs in the first {} records to estimate the insert size, are the reads paired-end? Set it with --max-insert",
+            sampled, seen
+        )));
+    }
+    let insert = InsertSize::estimate(reader.records(), n).ok_or_else(|| {
+        ExcordError::Usage(
+            "No proper pair to estimate the insert size, are the reads paired-end? Set it with --max-insert".to_string(),
+        )
+    })?;
+    let max_insert = insert.max_insert(n_sd);
+    eprintln!(
+        "{}Insert size from {} pairs: median {}, sd {:.1}, discordant above {} bp",
+        tag, insert.pairs, insert.median, insert.sd, max_insert
+    );
+    Ok(max_insert)
+}
 
-
-    // handel -o option
-    let t = cli.out;
-    let mut _outprefix_ancestors = t.ancestors().clone();
-    _outprefix_ancestors.next();
-    let mut _outprefix_parent = _outprefix_ancestors.next().unwrap().to_path_buf();
-    let _outprefix_parent_abs = absolute_path(_outprefix_parent).unwrap();
-    if !_outprefix_parent_abs.is_dir() {
-        println!(
-            "Output directory does not exists: {} ",
-            _outprefix_parent_abs.to_str().unwrap()
-        );
-        exit(1);
+/// Set the reference of a CRAM and check its contigs against the header.
+/// Without `-r` every sequence must be found from its `M5` in
+/// `REF_CACHE`/`REF_PATH` or from its `UR`.
+fn set_cram_reference<R: Read>(
+    bam: &mut R,
+    path: &Path,
+    reference: Option<&Path>,
+) -> Result<(), ExcordError> {
+    if InputFormat::of(bam) != Some(InputFormat::Cram) {
+        return Ok(());
     }
-    let mut f = BufWriter::new(File::create(t).unwrap());
+    let header = header_contigs(bam.header());
+    let Some(reference) = reference else {
+        return match RefPath::from_env().first_missing(&header) {
+            Some(sq) => Err(ExcordError::Usage(format!(
+                "{} is a CRAM file, the sequence of {}{} is not in REF_CACHE/REF_PATH, reference(-r) is required.",
+                path.display(),
+                sq.name,
+                sq.m5.as_ref().map(|x| format!(" (M5 {})", x)).unwrap_or_default()
+            ))),
+            None => Ok(()),
+        };
+    };
+    let contigs = fasta_contigs(reference).map_err(|msg| ExcordError::Open {
+        path: reference.to_path_buf(),
+        msg,
+    })?;
+    check_contigs(&header, &contigs).map_err(|e| {
+        ExcordError::Usage(format!(
+            "The reference {} does not match {}: {}",
+            reference.display(),
+            path.display(),
+            e
+        ))
+    })?;
+    unsafe { bam::set_fai_filename(bam.htsfile(), reference) }.map_err(open_error(reference))
+}
 
-    let mut bam = bam::Reader::from_path(&_bam).unwrap();
+/// Open a BAM/SAM/CRAM, `-` is the standard input. The format is detected from the content.
+fn open_reader(path: &Path, reference: Option<&Path>) -> Result<bam::Reader, ExcordError> {
+    let mut bam = if path == Path::new("-") {
+        bam::Reader::from_stdin()
+    } else {
+        bam::Reader::from_path(path)
+    }
+    .map_err(open_error(path))?;
+    set_cram_reference(&mut bam, path, reference)?;
+    Ok(bam)
+}
 
+/// One input and its outputs.
+struct Job {
+    bam: PathBuf,
+    /// file name of the input without extension
+    name: String,
+    out: PathBuf,
+    ins_fasta: Option<PathBuf>,
+    /// prefix of the messages, the input when there are several
+    tag: String,
+}
 
+/// Replace `{sample}` and `{name}` in an output path.
+fn expand_template(template: &Path, sample: &str, name: &str) -> PathBuf {
+    PathBuf::from(
+        template
+            .to_string_lossy()
+            .replace("{sample}", sample)
+            .replace("{name}", name),
+    )
+}
 
-    if _bam.to_str().unwrap().to_lowercase().contains(&".cram".to_string()) {
-        match cli.reference {
-            Some(reference)=>{
-                bam.set_reference(reference).unwrap();
-            },
-            None =>{
-                println!("excord-lr is running on CRAM file, reference(-r) is required.");
-                exit(1)
-            }
-        }
+/// `SM` of the header when there is exactly one sample.
+fn header_sample(header: &HeaderView) -> Option<String> {
+    let read_groups = ReadGroups::from_header(header);
+    let mut samples: Vec<&str> = read_groups
+        .ids()
+        .iter()
+        .filter_map(|x| read_groups.sample(x))
+        .collect();
+    samples.sort_unstable();
+    samples.dedup();
+    match samples[..] {
+        [sample] => Some(sample.to_string()),
+        _ => None,
     }
+}
 
-    
-
-    bam.set_threads(cli.thread).unwrap();
-    let mut record = Record::new();
-
-    while let Some(result) = bam.read(&mut record) {
-        // if str::from_utf8(&record.qname()).unwrap() == "4aa7ca6c-d970-4b23-90ec-6e449956685f".to_string() {
-        //     dbg!(&record);
-        // }
+/// # Inputs and their output paths
+///
+/// `{sample}` is the `SM` of the input when its header has exactly one
+/// sample, the file name otherwise. With several inputs the output paths must
+/// have a placeholder. The standard input(`-`) can only be the only input, its
+/// placeholders are replaced once its header has been read.
+fn plan_jobs(cli: &Cli) -> Result<Vec<Job>, ExcordError> {
+    let mut inputs = cli.bam.clone();
+    if let Some(list) = &cli.bam_list {
+        let text = std::fs::read_to_string(list).map_err(|e| ExcordError::Open {
+            path: list.clone(),
+            msg: e.to_string(),
+        })?;
+        inputs.extend(
+            text.lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                .map(PathBuf::from),
+        );
+    }
+    if inputs.is_empty() {
+        return Err(ExcordError::Usage("No input BAM/CRAM file".to_string()));
+    }
+    // only optional with a command.
+    let Some(cli_out) = cli.out.as_deref() else {
+        return Err(ExcordError::Usage("--out is required".to_string()));
+    };
+    let templated = |x: &Path| {
+        let x = x.to_string_lossy();
+        x.contains("{sample}") || x.contains("{name}")
+    };
+    if inputs.len() > 1 && !templated(cli_out) {
+        return Err(ExcordError::Usage(format!(
+            "{} inputs need {{sample}} or {{name}} in the output path: {}",
+            inputs.len(),
+            cli_out.display()
+        )));
+    }
+    if let Some(fasta) = cli
+        .ins_fasta
+        .as_ref()
+        .filter(|x| inputs.len() > 1 && !templated(x))
+    {
+        return Err(ExcordError::Usage(format!(
+            "{} inputs need {{sample}} or {{name}} in the --ins-fasta path: {}",
+            inputs.len(),
+            fasta.display()
+        )));
+    }
 
-        // dbg!(str::from_utf8(&record.qname()).unwrap());
+    if inputs.len() > 1 && inputs.iter().any(|x| x == Path::new("-")) {
+        return Err(ExcordError::Usage(
+            "The standard input(-) can only be the only input".to_string(),
+        ));
+    }
+    if cli_out == Path::new("-") && cli.split_by.is_some() {
+        return Err(ExcordError::Usage(
+            "--split-by needs an output file, not the standard output(-)".to_string(),
+        ));
+    }
 
-        match result {
-            Err(_) => break, //exit if the last one was processed.
-            Ok(()) => {}
-        }
-        if cli.exclude_secondary & record.is_secondary() {
-            // dbg!("found secondary", record.qname());
+    let mut jobs: Vec<Job> = vec![];
+    for bam in inputs {
+        if bam == Path::new("-") {
+            jobs.push(Job {
+                bam,
+                name: "stdin".to_string(),
+                out: cli_out.to_path_buf(),
+                ins_fasta: cli.ins_fasta.clone(),
+                tag: String::new(),
+            });
             continue;
         }
-
-        if cli.exclude_unmapped & record.is_unmapped() {
-            // dbg!("found unmapped", record.qname());
-            continue;
+        if !bam.is_file() {
+            return Err(ExcordError::Usage(format!(
+                "Invalid BAM file path: {}",
+                bam.display()
+            )));
         }
-
-        if record.mapq() < cli.mapq {
-            // dbg!("found low mapq", record.qname());
-            continue;
+        let name = bam
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let sample = if templated(cli_out) {
+            let reader = bam::Reader::from_path(&bam).map_err(open_error(&bam))?;
+            header_sample(reader.header()).unwrap_or_else(|| name.clone())
+        } else {
+            name.clone()
+        };
+        let out = expand_template(cli_out, &sample, &name);
+        if let Some(other) = jobs.iter().find(|x| x.out == out) {
+            return Err(ExcordError::Usage(format!(
+                "{} and {} have the same output path: {}",
+                other.bam.display(),
+                bam.display(),
+                out.display()
+            )));
         }
-
-        // dbg!(272u16.bitand(256u16));
-        if record.flags().bitand(cli.exclude_flag) != 0u16
-        // == 0 means not match with flag.
-        {
-            dbg!("found filtered flag", record.qname());
-            continue;
+        jobs.push(Job {
+            ins_fasta: cli
+                .ins_fasta
+                .as_ref()
+                .map(|x| expand_template(x, &sample, &name)),
+            tag: String::new(),
+            name,
+            out,
+            bam,
+        });
+    }
+    if jobs.len() > 1 {
+        for job in jobs.iter_mut() {
+            job.tag = format!("{}: ", job.bam.display());
         }
+    }
+    Ok(jobs)
+}
 
-        // if str::from_utf8(&record.qname()).unwrap() == "4aa7ca6c-d970-4b23-90ec-6e449956685f".to_string() {
-        //     dbg!(&record);
-        // }
+fn run(cli: Cli) -> Result<(), ExcordError> {
+    let jobs = plan_jobs(&cli)?;
 
-        let st = record.strand().to_owned(); // MUST move out of match, Because of mutable borrow by strand().
-                                             // Start to extact SA signals
-        let contig_name = record.contig();
-        let pos = record.pos();
-        let strand = match st {
-            ReqStrand::Forward => 1,
-            ReqStrand::Reverse => -1,
-        };
-        let mut alignments_event_vec: Vec<AlignmentEvent> = vec![];
-
-        match record.aux("SA".as_bytes()) {
-            Ok(_sa) => {
-                /* put the preliminary alignment to  */
-                // dbg!(&record.cigar());
-
-                let mut alignment_vec: Vec<SplitReadEvent> = Vec::new();
-                let mapq = record.mapq();
-                // let cigar_stats_nuc = record.cigar_stats_nucleotides();
-                let mut cigar_map = HashMap::from([
-                    ('D', 0),
-                    ('M', 0),
-                    ('I', 0),
-                    ('H', 0),
-                    ('S', 0),
-                    ('P', 0),
-                    ('X', 0),
-                    ('=', 0),
-                    ('N', 0),
-                ]);
-                /*
-                    Match(u32),    // M
-                    Ins(u32),      // I
-                    Del(u32),      // D
-                    RefSkip(u32),  // N
-                    SoftClip(u32), // S
-                    HardClip(u32), // H
-                    Pad(u32),      // P
-                    Equal(u32),    // =
-                    Diff(u32),     // X
-                    ps:
-                    Match(u32)
-                            |--------Type of this Enum
-                */
-                let mut first_cigar_str = "".to_string();
-                // let mut left_S:u32 = 0;
-                // let mut right_S:u32 = 0;
-                // let mut is_have_left_S:bool = false;
-                record.cigar().iter().for_each(|cigar| match cigar {
-                    &Cigar::Del(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"D".to_string();
-                        cigar_map.entry('D').and_modify(|e| *e += n.to_owned());
-                    }
-                    &Cigar::Match(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"M".to_string();
-                        cigar_map.entry('M').and_modify(|e| *e += n.to_owned());
-                        // dbg!(&n,&cigar_map);
-                    }
-                    &Cigar::Ins(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"I".to_string();
-                        cigar_map.entry('I').and_modify(|e| *e += n.to_owned());
-                    }
-                    &Cigar::RefSkip(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"N".to_string();
-                        cigar_map.entry('N').and_modify(|e| *e += n.to_owned());
-                    }
-                    &Cigar::SoftClip(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"S".to_string();
-                        cigar_map.entry('S').and_modify(|e| *e += n.to_owned());
-                        // if is_have_left_S == false{
-                        //     is_have_left_S = true;
-                        //     left_S = n.to_owned();
-                        // }else{
-                        //     right_S = n.to_owned();
-                        // }
-                    }
-                    &Cigar::HardClip(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"H".to_string();
-                        cigar_map.entry('H').and_modify(|e| *e += n.to_owned());
-                    }
-                    &Cigar::Pad(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"P".to_string();
-                        cigar_map.entry('P').and_modify(|e| *e += n.to_owned());
-                    }
-                    &Cigar::Equal(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"=".to_string();
-                        cigar_map.entry('=').and_modify(|e| *e += n.to_owned());
-                    }
-                    &Cigar::Diff(n) => {
-                        first_cigar_str += &n.to_owned().to_string();
-                        first_cigar_str += &"X".to_string();
-                        cigar_map.entry('X').and_modify(|e| *e += n.to_owned());
-                    }
-                });
-                // dbg!(&cigar_map,&first_cigar_str);
-                // dbg!(&contig_name,pos, std::str::from_utf8(record.qname()).unwrap(), &cigar_map);
-                alignment_vec.push(SplitReadEvent::new(
-                    &contig_name,
-                    &pos,
-                    cigar_map,
-                    &strand,
-                    &mapq,
-                    &first_cigar_str,
-                ));
-                /* process the supplementary alignments */
-                if let Aux::String(sa) = _sa {
-                    let sa_list = sa.split(";").collect::<Vec<&str>>();
-
-                    if sa_list.len() > cli.max_supp_alignm {
-                        continue;
-                    }
+    let mut regions: Vec<Region> = vec![];
+    for r in &cli.region {
+        regions.push(r.parse::<Region>().map_err(ExcordError::Usage)?);
+    }
+    if let Some(bed) = &cli.regions_bed {
+        regions.extend(read_regions_bed(bed).map_err(ExcordError::Usage)?);
+    }
 
-                    let sa_list_clean = sa_list.iter().filter(|x| x.len() > 0);
-                    // dbg!(&sa_list_clean);
-                    for single_sa in sa_list_clean {
-                        alignment_vec.push(parse_supplementary_alignment(single_sa));
-                    }
-                }
-                // dbg!(&std::str::from_utf8(record.qname()),&alignment_vec);
-                alignment_vec.sort_by(|a, b| splitter_order_cmp(a, b));
-
-                // iterally remove the potential FP that caused by the secondary alignment which is very close to
-                // the primary alignment.
-                // e.g.,  primary alignment reported region 1	2367366	2368042; the secondary alignment is 1	2367371	2368042
-                // this will introduce a false positive
-                //
-                // In order to remove it. next step I will compare each adjacent alignment pairs. if the overlap of the region
-                // is very long. one of them will be removed.
-                //
-
-                // process the large insertion
-                //
-                // Rules:
-                // 1. Only primary and one supplimentary alignment
-                // 2. Must have overlap
-                // 3. Both alignment should have a soft-clip more than 1kp.
-                // 4. Both alignment have same chormosome
-                if alignment_vec.len() == 2 {
-                    let a = alignment_vec.first().clone().unwrap();
-                    let b = alignment_vec.last().clone().unwrap();
-
-                    //2. Must have overlap
-
-                    // 3. Both alignment should have a soft-clip more than 1kp.
-                    if *a.cigar_map.get(&'S').unwrap() > cli.ins_clip_min || *a.cigar_map.get(&'H').unwrap() > cli.ins_clip_min {
-                        if a.chrom == b.chrom {
-                            if a.strand == b.strand {
-                                // if overlap > max_pct_overlap. default value of max_pct_overlap is 0 ,means each overlap pass the examination.
-                                if overlap(&a.start, &a.end, &b.start, &b.end, cli.max_pct_overlap)
-                                {
-                                    if *b.cigar_map.get(&'S').unwrap() > cli.ins_clip_min || *b.cigar_map.get(&'H').unwrap() > cli.ins_clip_min {
-                                        // will generate a new alignment event
-                                        
-
-                                        /*
-                                        Linking id (READNAME) = f2720f51-47ee-4dad-91d2-3f2aa26a66d0
-                                        Haplotype = 2
-                                        # alignments = 2
-                                        Total span = 46,603bp
-                                        Strands = ++
-                                        chr2:9,832,511-9,877,850 (+) = 45,339BP @MAPQ=60 NM=2886 CLIPPING=32S ... 8269S
-                                        chr2:9,877,576-9,879,114 (+) = 1,538BP @MAPQ=60 NM=191 CLIPPING=51421H ... 14H
-                                         */
-
-                                         // make sure the insertion presents in the middile of two segments
-                                        let mut pos_list = vec![a.start.clone(),a.end.clone(),b.start.clone(),b.end.clone()];
-
-                                        pos_list.sort();
-
-
-                                        let x = AlignmentEvent {
-                                            lchrom: a.chrom.clone(),
-                                            lstart: pos_list[0].clone() as u32,
-                                            lend: pos_list[1].clone() as u32,
-                                            lstrand: a.strand,
-                                            rchrom: b.chrom.clone(),
-                                            rstart: pos_list[1].clone() as u32,
-                                            rend: pos_list[1].clone() as u32, // TODO: should estimate the length of insertions and add it at here. [done]
-                                            rstrand: b.strand,
-                                            events_num: 1,
-                                            svtype: AlignEventType::Ins,
-                                        };
-
-                                        // alignment_vec.clear();
-                                        let rrr = get_alignment_event_record(
-                                            &x,
-                                            &cli.verbose,
-                                            &record,
-                                            &strand,
-                                            &"excord-lr-alignment-event-large-ins-two-alignments",
-                                        );
-
-                                        f.write(rrr.as_bytes()).unwrap();
-
-
-                                        let x = AlignmentEvent {
-                                            lchrom: a.chrom.clone(),
-                                            lstart: pos_list[0].clone() as u32,
-                                            lend: pos_list[2].clone() as u32,
-                                            lstrand: a.strand,
-                                            rchrom: b.chrom.clone(),
-                                            rstart: pos_list[2].clone() as u32,
-                                            rend: pos_list[2].clone() as u32, // TODO: should estimate the length of insertions and add it at here. [done]
-                                            rstrand: b.strand,
-                                            events_num: 1,
-                                            svtype: AlignEventType::Ins,
-                                        };
-
-                                        // alignment_vec.clear();
-                                        let rrr = get_alignment_event_record(
-                                            &x,
-                                            &cli.verbose,
-                                            &record,
-                                            &strand,
-                                            &"excord-lr-alignment-event-large-ins-two-alignments",
-                                        );
-
-                                        f.write(rrr.as_bytes()).unwrap();
-
-
-                                    }
-                                }
-                            }
-                        } else {
-                            let x = AlignmentEvent {
-                                lchrom: a.chrom.clone(),
-                                lstart: a.start as u32,
-                                lend: a.end as u32,
-                                lstrand: a.strand,
-                                rchrom: a.chrom.clone(),
-                                rstart: a.end as u32,
-                                rend: a.end as u32,
-                                rstrand: a.strand,
-                                events_num: 1,
-                                svtype: AlignEventType::Ins,
-                            };
-
-                            let rrr = get_alignment_event_record(
-                                &x,
-                                &cli.verbose,
-                                &record,
-                                &strand,
-                                &"excord-lr-alignment-event-large-ins-one-alignments",
-                            );
-
-                            f.write(rrr.as_bytes()).unwrap();
-                        }
-                    }
+    if cli.te_library.is_some() && !(1..=31).contains(&cli.te_kmer) {
+        return Err(ExcordError::Usage(format!(
+            "Invalid --te-kmer: {}, must be 1-31",
+            cli.te_kmer
+        )));
+    }
+    let te_library = match &cli.te_library {
+        Some(path) => Some(Arc::new(
+            TeLibrary::from_fasta(path, cli.te_kmer, cli.te_min_identity).map_err(|msg| {
+                ExcordError::Open {
+                    path: path.clone(),
+                    msg,
                 }
+            })?,
+        )),
+        None => None,
+    };
+
+    let contig_names = match (cli.contig_naming, &cli.contig_alias) {
+        (None | Some(ContigNaming::Alias), Some(path)) => ContigNames::from_alias_file(path)
+            .map_err(|msg| ExcordError::Open {
+                path: path.clone(),
+                msg,
+            })?,
+        (Some(ContigNaming::Alias), None) => {
+            return Err(ExcordError::Usage(
+                "--contig-naming alias needs an alias file(--contig-alias)".to_string(),
+            ))
+        }
+        (Some(naming), Some(_)) => {
+            return Err(ExcordError::Usage(format!(
+                "--contig-alias can not be used with --contig-naming {}",
+                naming
+            )))
+        }
+        (naming, None) => ContigNames::new(naming.unwrap_or_default()),
+    };
+    // the reference is checked once, the header of each input in `run_input`.
+    if let Some(reference) = &cli.reference {
+        let contigs = fasta_contigs(reference).map_err(|msg| ExcordError::Open {
+            path: reference.clone(),
+            msg,
+        })?;
+        contig_names
+            .check(contigs.iter().map(|x| x.0.as_str()))
+            .map_err(|e| ExcordError::Usage(format!("{}: {}", reference.display(), e)))?;
+    }
 
-                // process the super large insertion. e.g. a insertion larger than average length of long-read
-                //
-                // Rules:
-                // 1. Only primary alignment
-                // 3. Alignment should have a soft-clip more than 1kp.
-
-                if alignment_vec.len() == 1 {
-                    let a = alignment_vec.first().clone().unwrap();
-                    if *a.cigar_map.get(&'S').unwrap() > cli.ins_clip_min || *a.cigar_map.get(&'H').unwrap() > cli.ins_clip_min {
-                        // alignments_event_vec.push();
-                        let x = AlignmentEvent {
-                            lchrom: a.chrom.clone(),
-                            lstart: a.start as u32,
-                            lend: a.end as u32,
-                            lstrand: a.strand,
-                            rchrom: a.chrom.clone(),
-                            rstart: a.end as u32,
-                            rend: a.end as u32,
-                            rstrand: a.strand,
-                            events_num: 1,
-                            svtype: AlignEventType::Ins,
-                        };
-
-                        let rrr = get_alignment_event_record(
-                            &x,
-                            &cli.verbose,
-                            &record,
-                            &strand,
-                            &"excord-lr-alignment-event-large-ins",
-                        );
-
-                        f.write(rrr.as_bytes()).unwrap();
-                    }
-                }
+    let opts = ExtractorOptions {
+        mapq: cli.mapq,
+        exclude_flag: cli.exclude_flag,
+        exclude_secondary: cli.exclude_secondary,
+        exclude_unmapped: cli.exclude_unmapped,
+        indel_min: cli.indel_min,
+        merge_min: cli.merge_min,
+        ins_clip_min: cli.ins_clip_min,
+        not_merge: cli.not_merge,
+        split_only: cli.split_only,
+        split_types: cli.split_type.clone(),
+        max_pct_overlap: cli.max_pct_overlap,
+        max_supp_alignm: cli.max_supp_alignm,
+        clip_min: cli.clip_min,
+        max_insert: cli.max_insert,
+        ins_seq: cli.ins_seq || cli.ins_fasta.is_some() || te_library.is_some(),
+        te_library,
+        contig_names: Arc::new(contig_names),
+        on_error: cli.on_error,
+        debug: cli.debug,
+    };
+
+    let normal = match &cli.normal_bam {
+        Some(path) => Some(extract_normal(&cli, path, &regions, opts.clone())?),
+        None => None,
+    };
+
+    if jobs.len() == 1 {
+        return run_input(&cli, &jobs[0], &regions, opts, normal.as_ref());
+    }
+    // the inputs are taken in order by `--jobs` threads, a failed input does not stop the others.
+    let next_job = AtomicUsize::new(0);
+    let results: Mutex<Vec<(usize, Result<(), ExcordError>)>> = Mutex::new(vec![]);
+    thread::scope(|scope| {
+        for _ in 0..cli.jobs.clamp(1, jobs.len()) {
+            scope.spawn(|| loop {
+                let idx = next_job.fetch_add(1, Ordering::SeqCst);
+                let Some(job) = jobs.get(idx) else {
+                    break;
+                };
+                let result = run_input(&cli, job, &regions, opts.clone(), normal.as_ref());
+                results.lock().unwrap().push((idx, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|x| x.0);
+    let mut errors = results.into_iter().filter_map(|(idx, x)| {
+        Some(ExcordError::Input {
+            path: jobs[idx].bam.clone(),
+            source: Box::new(x.err()?),
+        })
+    });
+    let first = errors.next();
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    match first {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-                for i in 1..alignment_vec.len() {
-                    let j = i - 1;
-                    let a: &SplitReadEvent = &alignment_vec[j];
-                    let b: &SplitReadEvent = &alignment_vec[i];
-                    let bed_line: String;
-                    // dbg!(&a);
-                    if alignment_pos_cmp(a, b) == Ordering::Greater {
-                        bed_line = get_alignment_split_record(
-                            &b,
-                            &a,
-                            &cli.verbose,
-                            &record,
-                            &strand,
-                            &alignment_vec.len(),
-                            &"excord-lr-split-read",
-                        );
-                    } else {
-                        bed_line = get_alignment_split_record(
-                            &a,
-                            &b,
-                            &cli.verbose,
-                            &record,
-                            &strand,
-                            &alignment_vec.len(),
-                            &"excord-lr-split-read",
-                        );
-                    }
-                    f.write(bed_line.as_bytes()).unwrap();
-                }
-            }
-            Err(_) => {}
+/// Open an input, through its index when it is restricted to regions or split between workers.
+fn open_input(cli: &Cli, path: &Path, regions: &[Region]) -> Result<Input, ExcordError> {
+    let reference = cli.reference.as_deref();
+    Ok(if regions.is_empty() && cli.workers == 0 {
+        let mut bam = open_reader(path, reference)?;
+        bam.set_threads(cli.thread).map_err(open_error(path))?;
+        Input::Whole(Prefetched::new(bam))
+    } else {
+        if path == Path::new("-") {
+            return Err(ExcordError::Usage(
+                "--region, --regions-bed and --workers require an indexed BAM/CRAM, not the standard input".to_string(),
+            ));
+        }
+        let mut bam = bam::IndexedReader::from_path(path).map_err(|e| ExcordError::Open {
+            path: path.to_path_buf(),
+            msg: format!(
+                "--region, --regions-bed and --workers require an indexed BAM/CRAM: {}",
+                e
+            ),
+        })?;
+        set_cram_reference(&mut bam, path, reference)?;
+        let resolved = if regions.is_empty() {
+            whole_genome(bam.header())
+        } else {
+            resolve_regions(regions, bam.header()).map_err(ExcordError::Usage)?
         };
+        Input::Indexed(bam, resolved)
+    })
+}
 
-        // Extract Alignment event if split_only option is disabled.
-
-        if cli.split_only == false {
-            // parse the CIGAR value for each record
-            let cigar = record.cigar();
+/// Estimate the maximal insert size of `--short-read` input unless `--max-insert` is given.
+fn set_max_insert(
+    cli: &Cli,
+    path: &Path,
+    input: &mut Input,
+    opts: &mut ExtractorOptions,
+    tag: &str,
+) -> Result<(), ExcordError> {
+    let reference = cli.reference.as_deref();
+    if cli.short_read && cli.max_insert.is_none() {
+        let max_insert = match input {
+            Input::Whole(bam) => estimate_max_insert(bam, cli.insert_sample, cli.insert_sd, tag)?,
+            Input::Indexed(..) => estimate_max_insert(
+                &mut Prefetched::new(open_reader(path, reference)?),
+                cli.insert_sample,
+                cli.insert_sd,
+                tag,
+            )?,
+        };
+        opts.max_insert = Some(max_insert);
+    }
+    Ok(())
+}
 
-            // let alignment_event_vec:Vec<AlignmentPos> = parse_alignment_event(&cigar_string);
-            let mut total_consume = 0u32;
-            for x in cigar.into_iter() {
-                match x {
-                    &Cigar::Del(n) => {
-                        total_consume += n;
-                    }
-                    &Cigar::Match(n) => {
-                        total_consume += n;
-                    }
-                    &Cigar::RefSkip(n) => {
-                        total_consume += n;
-                    }
-                    &Cigar::Equal(n) => {
-                        total_consume += n;
-                    }
-                    _ => {}
-                }
+/// Extract the signals of an opened input, return the number of skipped records.
+fn extract_input(
+    cli: &Cli,
+    path: &Path,
+    input: Input,
+    opts: ExtractorOptions,
+    mut emit: impl FnMut(SvSignal) -> Result<(), ExcordError>,
+) -> Result<u64, ExcordError> {
+    let reference = cli.reference.as_deref();
+    Ok(match input {
+        Input::Whole(bam) => {
+            let mut extractor = SignalExtractor::new(bam, opts);
+            extractor.by_ref().try_for_each(|x| emit(x?))?;
+            extractor.skipped()
+        }
+        Input::Indexed(mut bam, resolved) => {
+            if cli.workers == 0 {
+                bam.set_threads(cli.thread).map_err(open_error(path))?;
+                let bam = RegionReader::from_chunks(bam, to_chunks(&resolved, None));
+                let mut extractor = SignalExtractor::new(bam, opts);
+                extractor.by_ref().try_for_each(|x| emit(x?))?;
+                extractor.skipped()
+            } else {
+                let chunks = to_chunks(&resolved, Some(cli.chunk_size));
+                extract_parallel(path, reference, &chunks, &opts, cli.workers, &mut emit)?
             }
+        }
+    })
+}
 
-            let mut left_consume = 0u32;
-            let mut right_consume = total_consume;
-            for x in cigar.into_iter() {
-                match x {
-                    &Cigar::Del(n) => {
-                        right_consume -= n;
-                        if n >= cli.indel_min {
-                            // report one event
-                            let aligments_event = AlignmentEvent::new(
-                                &contig_name,
-                                &left_consume,
-                                &right_consume,
-                                &n,
-                                &pos,
-                                &strand,
-                                Some(AlignEventType::Del),
-                            );
-                            alignments_event_vec.push(aligments_event);
-                        }
-                        left_consume += n;
-                    }
-                    &Cigar::Ins(n) => {
-                        if n >= cli.indel_min {
-                            let aligments_event = AlignmentEvent::new(
-                                &contig_name,
-                                &left_consume,
-                                &n, // for Insetions derived from CIGAR value, encode the length in right region.
-                                &(0u32),
-                                &pos,
-                                &strand,
-                                Some(AlignEventType::Ins),
-                            );
-                            // dbg!("insertion",&aligments_event);
-                            alignments_event_vec.push(aligments_event);
-                        }
-                        // do not recaculate the comsume, beacuse the INS does not take account for the reference.
-                        // right_consume -= n;
-                        // left_consume += n;
-                    }
-                    &Cigar::Match(n) => {
-                        left_consume += n;
-                        right_consume -= n;
-                    }
-                    &Cigar::RefSkip(n) => {
-                        left_consume += n;
-                        right_consume -= n;
-                    }
-                    &Cigar::Equal(n) => {
-                        left_consume += n;
-                        right_consume -= n;
-                    }
-                    _ => {}
-                }
-            }
+/// # Signals of the normal sample
+///
+/// The normal is extracted with the options and the regions of the tumor.
+fn extract_normal(
+    cli: &Cli,
+    path: &Path,
+    regions: &[Region],
+    mut opts: ExtractorOptions,
+) -> Result<NormalSignals, ExcordError> {
+    if path == Path::new("-") || !path.is_file() {
+        return Err(ExcordError::Usage(format!(
+            "Invalid normal BAM file path: {}",
+            path.display()
+        )));
+    }
+    let tag = format!("{}: ", path.display());
+    let mut input = open_input(cli, path, regions)?;
+    let header = match &input {
+        Input::Whole(bam) => bam.header_view().clone(),
+        Input::Indexed(bam, _) => bam.header().clone(),
+    };
+    let names: Vec<String> = (0..header.target_count())
+        .map(|tid| String::from_utf8_lossy(header.tid2name(tid)).into_owned())
+        .collect();
+    opts.contig_names
+        .check(names.iter().map(|x| x.as_str()))
+        .map_err(|e| ExcordError::Usage(format!("{}: {}", path.display(), e)))?;
+    set_max_insert(cli, path, &mut input, &mut opts, &tag)?;
+    let mut normal = NormalSignals::new(cli.normal_tolerance, cli.normal_size_similarity);
+    let skipped = extract_input(cli, path, input, opts, |x| {
+        normal.add(&x);
+        Ok(())
+    })?;
+    normal.finish();
+    if skipped > 0 {
+        eprintln!("{}Skipped {} invalid records", tag, skipped);
+    }
+    eprintln!("{}{} signals in the normal", tag, normal.len());
+    Ok(normal)
+}
 
-            // if str::from_utf8(&record.qname()).unwrap() == "4aa7ca6c-d970-4b23-90ec-6e449956685f".to_string() {
-            //     dbg!(&alignments_event_vec);
-            //     dbg!(&alignments_event_vec.len());
-
-            // }
-
-            // do merge step
-            let mut merged_alignments_event_vec: Vec<AlignmentEvent> = vec![];
-
-            // merge two alignments if needed
-            if alignments_event_vec.len() == 2 {
-                let a: AlignmentEvent = alignments_event_vec[0].clone();
-                let b: AlignmentEvent = alignments_event_vec[1].clone();
-                if b.lend.abs_diff(a.rstart) < cli.merge_min
-                    && (a.svtype == AlignEventType::Del && b.svtype == AlignEventType::Del)
-                {
-                    let c: AlignmentEvent = AlignmentEvent {
-                        lchrom: a.lchrom,
-                        lstart: a.lstart,
-                        lend: a.lend,
-                        lstrand: a.lstrand,
-                        rchrom: b.rchrom,
-                        rstart: b.rstart,
-                        rend: b.rend,
-                        rstrand: b.rstrand,
-                        events_num: 1, // hard code 1, STIX will parse it as a split-event.
-                        svtype: AlignEventType::Del,
-                    };
-
-                    merged_alignments_event_vec.push(c);
-                } else {
-                    merged_alignments_event_vec.push(a);
-                    merged_alignments_event_vec.push(b);
-                }
-            } else if alignments_event_vec.len() > 2 {
-                let mut merge1: Vec<AlignmentEvent> = alignments_event_vec.clone();
-                let mut merge2: Vec<AlignmentEvent> = vec![];
-                let mut iter_times = 5u32;
-
-                // try to merge several times to handle more than two adjecent dels.
-                // 1. merge 5 times, each time will based on the previous one.
-                // 2. If the original vec is length as n, we iterally compare previous and next for the 2 to n-1 elements.
-                // 3. Only works in Del
-
-                // each round
-                //  [ a, b, c, d, e]
-                //    |--|--| -> idx =1
-                //       |--|--| --> idx =2
-                //          |--|--| --> idx =3 
-
-                // 5 round in total
-                // [ a, b, c, d, e] --> init
-                // [ ab, c,d,e] --> round 1
-                // [ abc, d, e] --> round 2
-                // ...
-                // round 5 ----> merged_alignment_vec and go down.
-
-                loop {
-                    iter_times -= 1;
-                    let mut idx = 1usize;
-
-                    loop {
-                        if idx > (&merge1.len() - 2) {
-                            break;
-                        }
-                        let pidx = idx - 1;
-                        let nidx = idx + 1;
-                        let prv: AlignmentEvent = merge1[pidx].clone();
-                        let target: AlignmentEvent = merge1[idx].clone();
-                        let nxt: AlignmentEvent = merge1[nidx].clone();
-
-                        if (prv.lend.abs_diff(target.rstart) < cli.merge_min
-                            && (target.svtype == AlignEventType::Del
-                                && prv.svtype == AlignEventType::Del))
-                            || (target.lend.abs_diff(nxt.rstart) < cli.merge_min
-                                && (target.svtype == AlignEventType::Del
-                                    && nxt.svtype == AlignEventType::Del))
-                        {
-                            if prv.lend.abs_diff(target.rstart) < cli.merge_min
-                                && (target.svtype == AlignEventType::Del
-                                    && prv.svtype == AlignEventType::Del)
-                            {
-                                let c: AlignmentEvent = AlignmentEvent {
-                                    lchrom: prv.lchrom,
-                                    lstart: prv.lstart,
-                                    lend: prv.lend,
-                                    lstrand: prv.lstrand,
-                                    rchrom: target.rchrom,
-                                    rstart: target.rstart,
-                                    rend: target.rend,
-                                    rstrand: target.rstrand,
-                                    events_num: 1, // hard code 1, STIX will parse it as a split-event.
-                                    svtype: AlignEventType::Del,
-                                };
-                                // merge2.push(c);
-                                merge2.push(c);
-                            }
-
-                            if target.lend.abs_diff(nxt.rstart) < cli.merge_min
-                                && (target.svtype == AlignEventType::Del
-                                    && nxt.svtype == AlignEventType::Del)
-                            {
-                                let c: AlignmentEvent = AlignmentEvent {
-                                    lchrom: target.lchrom,
-                                    lstart: target.lstart,
-                                    lend: target.lend,
-                                    lstrand: target.lstrand,
-                                    rchrom: nxt.rchrom,
-                                    rstart: nxt.rstart,
-                                    rend: nxt.rend,
-                                    rstrand: nxt.rstrand,
-                                    events_num: 1, // hard code 1, STIX will parse it as a split-event.
-                                    svtype: AlignEventType::Del,
-                                };
-                                // merge2.push(c);
-                                merge2.push(c);
-                            }
-                            idx += 1;
-                        } else {
-                            if idx == 1 {
-                                merge2.push(prv);
-                                merge2.push(target);
-                                merge2.push(nxt);
-                            } else {
-                                merge2.push(nxt);
-                            }
-
-                            idx += 1;
-                        }
-                    }
+/// Extract the signals of one input.
+fn run_input(
+    cli: &Cli,
+    job: &Job,
+    regions: &[Region],
+    mut opts: ExtractorOptions,
+    normal: Option<&NormalSignals>,
+) -> Result<(), ExcordError> {
+    let _bam = job.bam.clone();
+    let reference = cli.reference.as_deref();
+
+    let mut input = open_input(cli, &_bam, regions)?;
+    // the reads spanning the breakpoints are fetched once the signals are known.
+    let ref_reader = if cli.ref_support {
+        if _bam == Path::new("-") {
+            return Err(ExcordError::Usage(
+                "--ref-support requires an indexed BAM/CRAM, not the standard input".to_string(),
+            ));
+        }
+        let mut bam = bam::IndexedReader::from_path(&_bam).map_err(|e| ExcordError::Open {
+            path: _bam.clone(),
+            msg: format!("--ref-support requires an indexed BAM/CRAM: {}", e),
+        })?;
+        set_cram_reference(&mut bam, &_bam, reference)?;
+        Some(bam)
+    } else {
+        None
+    };
+    let header = match &input {
+        Input::Whole(bam) => bam.header_view().clone(),
+        Input::Indexed(bam, _) => bam.header().clone(),
+    };
+
+    // the placeholders of the standard input are only known now.
+    let sample = header_sample(&header).unwrap_or_else(|| job.name.clone());
+    let t = expand_template(&job.out, &sample, &job.name);
+    let ins_fasta = job
+        .ins_fasta
+        .as_ref()
+        .map(|x| expand_template(x, &sample, &job.name));
 
-                    if merge1.len() == merge2.len() {
-                        break;
-                    } else if iter_times <= 0 {
-                        break;
-                    } else {
-                        merge1 = merge2.clone();
-                        merge2.clear();
-                    }
-                }
-                merged_alignments_event_vec = merge2.clone();
-
-                // if str::from_utf8(&record.qname()).unwrap()
-                //     == "4aa7ca6c-d970-4b23-90ec-6e449956685f".to_string()
-                // {
-                //     dbg!(&merged_alignments_event_vec);
-                //     dbg!(&merged_alignments_event_vec.len());
-                // }
-
-                // merged_alignments_event_vec.dedup_by(same_bucket)
-            } else if alignments_event_vec.len() == 1 {
-                let a: AlignmentEvent = alignments_event_vec[0usize].clone();
-                merged_alignments_event_vec.push(a);
+    // handel -o option
+    if t != Path::new("-") {
+        let mut _outprefix_ancestors = t.ancestors();
+        _outprefix_ancestors.next();
+        let _outprefix_parent = _outprefix_ancestors.next().unwrap_or(Path::new(""));
+        let _outprefix_parent_abs =
+            absolute_path(_outprefix_parent).map_err(|e| ExcordError::Write {
+                path: t.clone(),
+                source: e,
+            })?;
+        if !_outprefix_parent_abs.is_dir() {
+            return Err(ExcordError::Usage(format!(
+                "Output directory does not exists: {}",
+                _outprefix_parent_abs.display()
+            )));
+        }
+    }
+
+    set_max_insert(cli, &_bam, &mut input, &mut opts, &job.tag)?;
+
+    let names: Vec<String> = (0..header.target_count())
+        .map(|tid| String::from_utf8_lossy(header.tid2name(tid)).into_owned())
+        .collect();
+    opts.contig_names
+        .check(names.iter().map(|x| x.as_str()))
+        .map_err(|e| ExcordError::Usage(format!("{}: {}", _bam.display(), e)))?;
+    let contig_names = opts.contig_names.clone();
+    let contigs: Vec<String> = names
+        .iter()
+        .map(|x| contig_names.rename(x).into_owned())
+        .collect();
+    let max_mem = cli.sort_mem.checked_mul(1 << 20).ok_or_else(|| {
+        ExcordError::Usage(format!("--sort-mem {} MiB is too large", cli.sort_mem))
+    })?;
+    let mut out_opts = OutputOptions {
+        preset: match cli.format {
+            Format::Bed => IndexPreset::Bed,
+            Format::Vcf => IndexPreset::Vcf,
+        },
+        index: if cli.csi {
+            IndexFormat::Csi
+        } else {
+            IndexFormat::Tbi
+        },
+        sort: cli.sort,
+        max_mem,
+        ..Default::default()
+    };
+    if let Some(tmp_dir) = &cli.tmp_dir {
+        out_opts.tmp_dir = tmp_dir.clone();
+    }
+    let max_len = (0..header.target_count())
+        .map(|tid| contig_len(&header, tid))
+        .max()
+        .unwrap_or(0);
+    if t.extension().is_some_and(|x| x == "gz")
+        && out_opts.index == IndexFormat::Tbi
+        && max_len > IndexFormat::TBI_MAX_POS
+    {
+        eprintln!(
+            "{}Contigs longer than {} bp can not be indexed by TBI, a CSI index is written",
+            job.tag,
+            IndexFormat::TBI_MAX_POS
+        );
+        out_opts.index = IndexFormat::Csi;
+    }
+    let write_error = |e| ExcordError::Write {
+        path: t.clone(),
+        source: e,
+    };
+    let mut open = |name: &str| {
+        let path = group_path(&t, name);
+        let writer = FormatWriter::create(
+            &path,
+            cli,
+            &header,
+            &out_opts,
+            contigs.clone(),
+            &contig_names,
+        )?;
+        Ok((path, writer))
+    };
+    let mut out = match cli.split_by {
+        None => Outputs::Single(FormatWriter::create(
+            &t,
+            cli,
+            &header,
+            &out_opts,
+            contigs.clone(),
+            &contig_names,
+        )?),
+        Some(by) => Outputs::Groups(GroupOutput::create(
+            by,
+            &ReadGroups::from_header(&header),
+            &mut open,
+        )?),
+    };
+    let mut fasta = match &ins_fasta {
+        Some(path) => {
+            let f = File::create(path).map_err(|e| ExcordError::Write {
+                path: path.clone(),
+                source: e,
+            })?;
+            Some(InsFastaWriter::new(BufWriter::new(f)))
+        }
+        None => None,
+    };
+    let fasta_error = |e| ExcordError::Write {
+        path: ins_fasta.clone().unwrap_or_default(),
+        source: e,
+    };
+    let mut write = |x: &SvSignal, annotation: &Annotation| {
+        if let Some(fasta) = fasta.as_mut() {
+            fasta.write_signal(x).map_err(fasta_error)?;
+        }
+        match &mut out {
+            Outputs::Single(writer) => writer.write_signal(x, annotation).map_err(write_error),
+            Outputs::Groups(groups) => groups.write_signal(x, annotation, &mut open),
+        }
+    };
+    let mut dedup = cli.dedup_split.then(|| SplitDedup::new(&contigs));
+    // signals with more normal support than --max-normal-support are not written.
+    let mut in_normal = 0u64;
+    let is_in_normal = |x: &Annotation| x.normal.is_some_and(|n| n > cli.max_normal_support);
+    // with --ref-support the signals are written once the reads at their breakpoints are counted.
+    let mut ref_support = ref_reader.map(|bam| {
+        RefSupport::new(
+            bam,
+            &contigs,
+            opts.clone(),
+            RefSupportOptions {
+                flank: cli.ref_flank,
+                slop: cli.ref_slop,
+            },
+        )
+    });
+    let ref_error = |source| ExcordError::Read {
+        after: None,
+        source,
+    };
+    let mut counted: Vec<(SvSignal, Annotation, AlleleCounts)> = vec![];
+    let mut emit = |x: SvSignal| {
+        if dedup.as_mut().is_some_and(|d| !d.keep(&x)) {
+            return Ok(());
+        }
+        let annotation = Annotation {
+            counts: None,
+            normal: normal.map(|n| n.support(&x)),
+        };
+        if is_in_normal(&annotation) {
+            in_normal += 1;
+            return Ok(());
+        }
+        match ref_support.as_mut() {
+            Some(ref_support) => {
+                ref_support
+                    .push(x, annotation, &mut counted)
+                    .map_err(ref_error)?;
+                counted.drain(..).try_for_each(|(x, annotation, counts)| {
+                    write(
+                        &x,
+                        &Annotation {
+                            counts: Some(counts),
+                            ..annotation
+                        },
+                    )
+                })
             }
+            None => write(&x, &annotation),
+        }
+    };
+
+    let skipped = extract_input(cli, &_bam, input, opts, &mut emit)?;
+    if let Some(ref_support) = ref_support {
+        ref_support.finish(&mut counted).map_err(ref_error)?;
+        for (x, annotation, counts) in counted {
+            write(
+                &x,
+                &Annotation {
+                    counts: Some(counts),
+                    ..annotation
+                },
+            )?;
+        }
+    }
+    if let Some(mut fasta) = fasta {
+        fasta.finish().map_err(fasta_error)?;
+    }
+    match out {
+        Outputs::Single(writer) => writer.finish().map_err(write_error)?,
+        Outputs::Groups(groups) => {
+            let manifest = t.with_file_name(format!("{}.manifest.tsv", split_extension(&t).0));
+            groups.finish(&manifest)?
+        }
+    }
+    if let Some(dedup) = dedup {
+        eprintln!(
+            "{}Suppressed {} duplicate split-read signals",
+            job.tag, dedup.suppressed
+        );
+    }
+    if normal.is_some() {
+        eprintln!(
+            "{}Removed {} signals found in the normal",
+            job.tag, in_normal
+        );
+    }
+    if skipped > 0 {
+        eprintln!("{}Skipped {} invalid records", job.tag, skipped);
+    }
+    Ok(())
+}
 
-            merged_alignments_event_vec.into_iter().for_each(|x| {
-                let rrr = get_alignment_event_record(
-                    &x,
-                    &cli.verbose,
-                    &record,
-                    &strand,
-                    &"excord-lr-alignment-event",
-                );
+/// # Cluster the signals of a BED output into SV calls
+///
+/// The input is read line by line, only the clusters around the current
+/// position are kept in memory.
+fn run_cluster(args: &ClusterCli) -> Result<(), ExcordError> {
+    let read_error = |e: io::Error| ExcordError::Open {
+        path: args.input.clone(),
+        msg: e.to_string(),
+    };
+    let write_error = |e| ExcordError::Write {
+        path: args.out.clone(),
+        source: e,
+    };
+    let input = open_bed(&args.input).map_err(read_error)?;
+    let mut out =
+        Output::create(&args.out, &OutputOptions::default(), vec![]).map_err(write_error)?;
+    writeln!(out, "{}", SvCall::HEADER).map_err(write_error)?;
+
+    let mut clusterer = Clusterer::new(ClusterOptions {
+        max_distance: args.max_distance,
+        min_size_similarity: args.min_size_similarity,
+        min_support: args.min_support,
+    });
+    let mut calls = vec![];
+    let mut columns = BedColumns::default();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(read_error)?;
+        if line.starts_with('#') {
+            columns = BedColumns::from_header(&line).unwrap_or(columns);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid =
+            |e: String| ExcordError::Usage(format!("{}:{}: {}", args.input.display(), i + 1, e));
+        let record = SignalRecord::parse(&line, &columns).map_err(invalid)?;
+        clusterer.push(&record, &mut calls).map_err(invalid)?;
+        for x in calls.drain(..) {
+            x.write_bed(&mut out).map_err(write_error)?;
+        }
+    }
+    clusterer.finish(&mut calls);
+    for x in calls {
+        x.write_bed(&mut out).map_err(write_error)?;
+    }
+    out.finish().map_err(write_error)
+}
 
-                f.write(rrr.as_bytes()).unwrap();
-            });
+/// # Count the signals supporting each SV
+///
+/// The signals are loaded into one interval tree per contig, then the SVs
+/// are read one by one.
+fn run_query(args: &QueryCli) -> Result<(), ExcordError> {
+    let read_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |e: io::Error| ExcordError::Open {
+            path: path.clone(),
+            msg: e.to_string(),
+        }
+    };
+    let invalid = |path: &Path, i: usize| {
+        let at = format!("{}:{}", path.display(), i + 1);
+        move |e: String| ExcordError::Usage(format!("{}: {}", at, e))
+    };
+    let write_error = |e| ExcordError::Write {
+        path: args.out.clone(),
+        source: e,
+    };
+
+    let mut records = vec![];
+    let mut columns = BedColumns::default();
+    let input = open_bed(&args.input).map_err(read_error(&args.input))?;
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(read_error(&args.input))?;
+        if line.starts_with('#') {
+            columns = BedColumns::from_header(&line).unwrap_or(columns);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(SignalRecord::parse(&line, &columns).map_err(invalid(&args.input, i))?);
+    }
+    let index = SignalIndex::new(records);
+
+    let mut out =
+        Output::create(&args.out, &OutputOptions::default(), vec![]).map_err(write_error)?;
+    writeln!(out, "{}", SvQuery::HEADER).map_err(write_error)?;
+    let mut format = args.sv_format;
+    let svs = open_bed(&args.sv).map_err(read_error(&args.sv))?;
+    for (i, line) in svs.lines().enumerate() {
+        let line = line.map_err(read_error(&args.sv))?;
+        if line.starts_with('#') {
+            if line.starts_with("##fileformat=VCF") || line.starts_with("#CHROM") {
+                format.get_or_insert(SvFormat::Vcf);
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
         }
-        alignments_event_vec.clear();
+        let sv = format
+            .get_or_insert(SvFormat::Bedpe)
+            .parse_line(&line)
+            .map_err(invalid(&args.sv, i))?;
+        let support = index.count(&sv, args.slop);
+        sv.write_bed(&mut out, &support).map_err(write_error)?;
     }
+    out.finish().map_err(write_error)
 }
@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::signal::{SignalType, SplitType, SvSignal};
+
+/// Signal type, SV type of split reads and discordant pairs, contig of the first breakpoint.
+type Key = (SignalType, Option<SplitType>, String);
+
+struct Entry {
+    /// first breakpoint
+    pos: i64,
+    /// length of a CIGAR deletion or insertion
+    len: Option<i64>,
+    /// the other breakpoints, contig and position
+    rest: Vec<(String, i64)>,
+    read: String,
+}
+
+/// # Signals of the normal sample of a tumor/normal pair
+///
+/// A normal signal matches a tumor signal when it has the same signal type,
+/// the same SV type for split reads and discordant pairs, and each of its
+/// breakpoints is on the same contig and within `tolerance` bp of the
+/// breakpoint of the tumor signal(see [`SvSignal::breakpoints`]). A CIGAR
+/// deletion or insertion also needs a length of at least
+/// `min_size_similarity` of the larger one, so a small normal indel does not
+/// hide a larger tumor one at the same place. The support of a tumor signal
+/// in the normal is the number of distinct normal reads with a matching
+/// signal.
+pub struct NormalSignals {
+    index: HashMap<Key, Vec<Entry>>,
+    tolerance: i64,
+    min_size_similarity: f64,
+    len: usize,
+}
+
+fn key(x: &SvSignal, chrom: &str) -> Key {
+    (x.signal_type(), x.split_type(), chrom.to_string())
+}
+
+/// Length of a CIGAR deletion or insertion, `None` for the other signals.
+fn indel_len(x: &SvSignal) -> Option<i64> {
+    match x {
+        SvSignal::CigarDel { len, .. } | SvSignal::CigarIns { len, .. } => Some(*len),
+        SvSignal::Split { .. }
+        | SvSignal::Discordant { .. }
+        | SvSignal::ClipInsOneSegment { .. }
+        | SvSignal::ClipInsTwoSegment { .. }
+        | SvSignal::Clip { .. } => None,
+    }
+}
+
+impl NormalSignals {
+    pub fn new(tolerance: i64, min_size_similarity: f64) -> NormalSignals {
+        NormalSignals {
+            index: HashMap::new(),
+            tolerance,
+            min_size_similarity,
+            len: 0,
+        }
+    }
+
+    /// Add a signal of the normal, call [`NormalSignals::finish`] once they are all added.
+    pub fn add(&mut self, x: &SvSignal) {
+        let breakpoints = x.breakpoints();
+        let Some(((chrom, pos), rest)) = breakpoints.split_first() else {
+            return;
+        };
+        self.index.entry(key(x, chrom)).or_default().push(Entry {
+            pos: *pos,
+            len: indel_len(x),
+            rest: rest.iter().map(|(c, p)| (c.to_string(), *p)).collect(),
+            read: x.read().qname.clone(),
+        });
+        self.len += 1;
+    }
+
+    /// Sort the signals by position.
+    pub fn finish(&mut self) {
+        for x in self.index.values_mut() {
+            x.sort_unstable_by_key(|x| x.pos);
+        }
+    }
+
+    /// Number of signals of the normal.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of distinct normal reads with a signal matching `x`.
+    pub fn support(&self, x: &SvSignal) -> usize {
+        let breakpoints = x.breakpoints();
+        let Some(((chrom, pos), rest)) = breakpoints.split_first() else {
+            return 0;
+        };
+        let Some(entries) = self.index.get(&key(x, chrom)) else {
+            return 0;
+        };
+        let near = |a: i64, b: i64| (a - b).abs() <= self.tolerance;
+        let len = indel_len(x);
+        let similar = |other: Option<i64>| match (len, other) {
+            (Some(a), Some(b)) if a > 0 && b > 0 => {
+                a.min(b) as f64 / a.max(b) as f64 >= self.min_size_similarity
+            }
+            _ => true,
+        };
+        let i = entries.partition_point(|e| e.pos < pos - self.tolerance);
+        entries[i..]
+            .iter()
+            .take_while(|e| e.pos <= pos + self.tolerance)
+            .filter(|e| {
+                similar(e.len)
+                    && e.rest.len() == rest.len()
+                    && e.rest
+                        .iter()
+                        .zip(rest)
+                        .all(|(a, b)| a.0 == b.0 && near(a.1, b.1))
+            })
+            .map(|e| e.read.as_str())
+            .collect::<HashSet<&str>>()
+            .len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aligments_event::{AlignEventType, AlignmentEvent},
+        signal::ReadInfo,
+        utils::{classify_split, parse_supplementary_alignment},
+    };
+
+    fn read(qname: &str) -> ReadInfo {
+        ReadInfo {
+            qname: qname.to_string(),
+            strand: 1,
+            flags: 0,
+            mapq: 60,
+            tid: 0,
+            pos: 0,
+            read_group: None,
+            sample: None,
+        }
+    }
+
+    /// CIGAR deletion of `qname` from `pos` to `pos + len`.
+    fn del(qname: &str, pos: i64, len: i64) -> SvSignal {
+        SvSignal::CigarDel {
+            read: read(qname),
+            event: AlignmentEvent {
+                lchrom: "1".to_string(),
+                lstart: pos - 100,
+                lend: pos,
+                lstrand: 1,
+                rchrom: "1".to_string(),
+                rstart: pos + len,
+                rend: pos + len + 100,
+                rstrand: 1,
+                events_num: 1,
+                svtype: AlignEventType::Del,
+            },
+            len,
+        }
+    }
+
+    fn split(qname: &str, a: &str, b: &str) -> SvSignal {
+        let left = parse_supplementary_alignment(a).unwrap();
+        let right = parse_supplementary_alignment(b).unwrap();
+        let (sv_type, orientation) = classify_split(&left, &right);
+        SvSignal::Split {
+            read: read(qname),
+            left,
+            right,
+            segments: 1,
+            sv_type,
+            orientation,
+        }
+    }
+
+    fn normal(signals: &[SvSignal]) -> NormalSignals {
+        let mut normal = NormalSignals::new(10, 0.7);
+        for x in signals {
+            normal.add(x);
+        }
+        normal.finish();
+        normal
+    }
+
+    #[test]
+    fn counts_distinct_reads_within_the_tolerance() {
+        let n = normal(&[
+            del("n1", 1005, 1000),
+            del("n1", 995, 1000),
+            del("n2", 1010, 1000),
+            del("n3", 1011, 1000),
+        ]);
+        assert_eq!(n.len(), 4);
+        assert_eq!(n.support(&del("t", 1000, 1000)), 2);
+        // the second breakpoint must be near too.
+        assert_eq!(n.support(&del("t", 1000, 1040)), 0);
+        assert_eq!(n.support(&del("t", 5000, 1000)), 0);
+    }
+
+    #[test]
+    fn indels_need_a_similar_length() {
+        let mut n = NormalSignals::new(30, 0.7);
+        n.add(&del("n1", 1000, 10));
+        n.finish();
+        // both breakpoints are within the tolerance, the lengths are not similar.
+        assert_eq!(n.support(&del("t", 1000, 30)), 0);
+        assert_eq!(n.support(&del("t", 1000, 14)), 1);
+        assert_eq!(n.support(&del("t", 1000, 7)), 1);
+        assert_eq!(n.support(&del("t", 1000, 6)), 0);
+    }
+
+    #[test]
+    fn signal_and_sv_types_must_match() {
+        let a = "1,1001,+,100M100S,60,0";
+        let n = normal(&[
+            split("n1", a, "1,5001,+,100S100M,60,0"),
+            del("n2", 1100, 3900),
+        ]);
+        assert_eq!(n.support(&split("t", a, "1,5006,+,100S100M,60,0")), 1);
+        // same breakpoints as an inversion.
+        assert_eq!(n.support(&split("t", a, "1,4901,-,100M100S,60,0")), 0);
+    }
+}
@@ -0,0 +1,441 @@
+use rust_htslib::htslib;
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CString,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use crate::sort::{ExternalSorter, SortRecord};
+
+/// # Columns of a record used by the index
+///
+/// Same presets as `tabix -p bed` and `tabix -p vcf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexPreset {
+    /// contig, 0-based start and end in the first three columns
+    Bed,
+    /// contig and 1-based POS, the end is `END` of INFO
+    Vcf,
+}
+
+impl IndexPreset {
+    /// `tbx_conf_t` of the preset: preset, sc, bc, ec, meta_char, line_skip.
+    fn conf(&self) -> [i32; 6] {
+        match self {
+            IndexPreset::Bed => [htslib::TBX_UCSC as i32, 1, 2, 3, b'#' as i32, 0],
+            IndexPreset::Vcf => [htslib::TBX_VCF as i32, 1, 2, 0, b'#' as i32, 0],
+        }
+    }
+
+    /// Contig and 0-based half-open interval of a record line.
+    fn parse(&self, line: &str) -> Option<(String, i64, i64)> {
+        let mut cols = line.split('\t');
+        let chrom = cols.next()?.to_string();
+        match self {
+            IndexPreset::Bed => {
+                let beg = cols.next()?.parse::<i64>().ok()?;
+                let end = cols.next()?.parse::<i64>().ok()?;
+                Some((chrom, beg, end.max(beg + 1)))
+            }
+            IndexPreset::Vcf => {
+                let beg = cols.next()?.parse::<i64>().ok()? - 1;
+                let info = cols.nth(5)?;
+                let end = info
+                    .split(';')
+                    .find_map(|x| x.strip_prefix("END="))
+                    .and_then(|x| x.parse::<i64>().ok())
+                    .unwrap_or(beg + 1);
+                Some((chrom, beg, end.max(beg + 1)))
+            }
+        }
+    }
+}
+
+/// # Index written next to a compressed output
+///
+/// A TBI index can not hold positions beyond [`IndexFormat::TBI_MAX_POS`], a
+/// CSI index has as many levels as the longest contig needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    Tbi,
+    Csi,
+}
+
+impl IndexFormat {
+    /// Largest position of a TBI index, 2^29.
+    pub const TBI_MAX_POS: i64 = 1 << 29;
+
+    const MIN_SHIFT: i32 = 14;
+
+    /// Number of levels of the binning index able to hold `max_end`.
+    fn n_lvls(&self, max_end: i64) -> i32 {
+        match self {
+            IndexFormat::Tbi => 5,
+            IndexFormat::Csi => {
+                // same default as tabix, more levels for longer contigs.
+                let mut n_lvls = (htslib::TBX_MAX_SHIFT as i32 - Self::MIN_SHIFT + 2) / 3;
+                while (1i64 << (Self::MIN_SHIFT + 3 * n_lvls)) < max_end {
+                    n_lvls += 1;
+                }
+                n_lvls
+            }
+        }
+    }
+}
+
+/// # Options of the output file
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub preset: IndexPreset,
+    pub index: IndexFormat,
+    /// Sort a plain output, a compressed output is always sorted
+    pub sort: bool,
+    /// Memory used to buffer records before a sorted run is spilled to disk
+    pub max_mem: usize,
+    /// Directory of the spilled runs
+    pub tmp_dir: PathBuf,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            preset: IndexPreset::Bed,
+            index: IndexFormat::Tbi,
+            sort: false,
+            max_mem: 768 << 20,
+            tmp_dir: env::temp_dir(),
+        }
+    }
+}
+
+/// # Destination of the formatted records
+///
+/// A path ending with `.gz` is written as coordinate-sorted BGZF with a
+/// `.tbi`/`.csi` index, any other path as plain text, sorted with
+/// `OutputOptions::sort` and in the order of the records otherwise. `-` is
+/// the standard output, as plain text. `finish` must be called once
+/// everything has been written.
+pub enum Output {
+    Plain(BufWriter<Box<dyn Write>>),
+    Sorted(Box<SortedOutput>),
+}
+
+impl Output {
+    /// `contigs` gives the order of the contigs in the sorted output, contigs
+    /// not in this list are written after them.
+    pub fn create(path: &Path, opts: &OutputOptions, contigs: Vec<String>) -> io::Result<Output> {
+        let sink = if path.extension().is_some_and(|x| x == "gz") {
+            Sink::Bgzf(BgzfFile::create(path, opts.index)?)
+        } else {
+            let f: Box<dyn Write> = if path == Path::new("-") {
+                Box::new(io::stdout())
+            } else {
+                Box::new(File::create(path)?)
+            };
+            let f = BufWriter::new(f);
+            if !opts.sort {
+                return Ok(Output::Plain(f));
+            }
+            Sink::Plain(f)
+        };
+        let contig_rank = contigs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.clone(), i))
+            .collect();
+        Ok(Output::Sorted(Box::new(SortedOutput {
+            sink,
+            preset: opts.preset,
+            contigs,
+            contig_rank,
+            pending: vec![],
+            header: vec![],
+            max_end: 0,
+            sorter: ExternalSorter::new(opts.max_mem, &opts.tmp_dir),
+        })))
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut f) => f.flush(),
+            Output::Sorted(f) => f.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(f) => f.write(buf),
+            Output::Sorted(f) => f.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(f) => f.flush(),
+            Output::Sorted(f) => f.flush(),
+        }
+    }
+}
+
+enum Sink {
+    Plain(BufWriter<Box<dyn Write>>),
+    Bgzf(BgzfFile),
+}
+
+/// # Output sorted by contig and start
+///
+/// The header lines(starting with `#`) are kept aside and the records go
+/// through an [`ExternalSorter`], everything is written by `finish`. A BGZF
+/// output is indexed while the sorted records are compressed, like
+/// `sort | bgzip | tabix` in one pass.
+pub struct SortedOutput {
+    sink: Sink,
+    preset: IndexPreset,
+    contigs: Vec<String>,
+    contig_rank: HashMap<String, usize>,
+    pending: Vec<u8>,
+    header: Vec<String>,
+    /// largest end of the records, the size of the index depends on it.
+    max_end: i64,
+    sorter: ExternalSorter,
+}
+
+impl SortedOutput {
+    fn add_line(&mut self, line: String) -> io::Result<()> {
+        if line.starts_with('#') {
+            self.header.push(line);
+            return Ok(());
+        }
+        let (chrom, beg, end) = self
+            .preset
+            .parse(&line)
+            .filter(|(_, beg, _)| *beg >= 0)
+            .ok_or_else(|| io::Error::other(format!("Can not sort the record: {}", line)))?;
+        self.max_end = self.max_end.max(end);
+        let rank = match self.contig_rank.get(&chrom) {
+            Some(rank) => *rank,
+            None => {
+                let rank = self.contigs.len();
+                self.contigs.push(chrom.clone());
+                self.contig_rank.insert(chrom, rank);
+                rank
+            }
+        };
+        self.sorter.push(SortRecord {
+            rank,
+            beg,
+            end,
+            line,
+        })
+    }
+
+    /// Sort and write the records, then close the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let line = String::from_utf8_lossy(&self.pending).into_owned();
+            self.pending.clear();
+            self.add_line(line)?;
+        }
+        let contigs = self.contigs;
+        match self.sink {
+            Sink::Plain(mut f) => {
+                for line in self.header {
+                    writeln!(f, "{}", line)?;
+                }
+                self.sorter.finish(|x| writeln!(f, "{}", x.line))?;
+                f.flush()
+            }
+            Sink::Bgzf(mut f) => {
+                for line in self.header {
+                    f.write_line(&line)?;
+                }
+                f.start_index(self.max_end)?;
+                self.sorter
+                    .finish(|x| f.write_record(&x, &contigs[x.rank]))?;
+                f.finish(self.preset)
+            }
+        }
+    }
+}
+
+impl Write for SortedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let mut start = 0;
+        while let Some(n) = self.pending[start..].iter().position(|x| *x == b'\n') {
+            let line = String::from_utf8_lossy(&self.pending[start..start + n]).into_owned();
+            start += n + 1;
+            self.add_line(line)?;
+        }
+        self.pending.drain(..start);
+        Ok(buf.len())
+    }
+
+    /// The records are only written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// # BGZF file indexed while it is written
+struct BgzfFile {
+    path: PathBuf,
+    fp: *mut htslib::BGZF,
+    index: IndexFormat,
+    idx: *mut htslib::hts_idx_t,
+    /// contigs of the index, in the order of the records.
+    names: Vec<String>,
+}
+
+impl BgzfFile {
+    fn create(path: &Path, index: IndexFormat) -> io::Result<BgzfFile> {
+        let c_path = path_to_cstring(path)?;
+        let fp = unsafe { htslib::bgzf_open(c_path.as_ptr(), c"w".as_ptr()) };
+        if fp.is_null() {
+            return Err(io::Error::other(format!(
+                "Can not create {}",
+                path.display()
+            )));
+        }
+        Ok(BgzfFile {
+            path: path.to_path_buf(),
+            fp,
+            index,
+            idx: ptr::null_mut(),
+            names: vec![],
+        })
+    }
+
+    /// Virtual offset of the next byte, `bgzf_tell` of htslib.
+    fn tell(&self) -> u64 {
+        unsafe {
+            (((*self.fp).block_address << 16) | ((*self.fp).block_offset as i64 & 0xFFFF)) as u64
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        let n = unsafe { htslib::bgzf_write(self.fp, data.as_ptr() as *const _, data.len()) };
+        if n < 0 || n as usize != data.len() {
+            return Err(io::Error::other(format!(
+                "Failed to write {}",
+                self.path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_all(line.as_bytes())?;
+        self.write_all(b"\n")
+    }
+
+    fn fmt(&self) -> i32 {
+        match self.index {
+            IndexFormat::Tbi => htslib::HTS_FMT_TBI as i32,
+            IndexFormat::Csi => htslib::HTS_FMT_CSI as i32,
+        }
+    }
+
+    /// Start the index after the header lines.
+    fn start_index(&mut self, max_end: i64) -> io::Result<()> {
+        if self.index == IndexFormat::Tbi && max_end > IndexFormat::TBI_MAX_POS {
+            return Err(io::Error::other(format!(
+                "position {} is beyond the {} bp limit of a TBI index, use a CSI index",
+                max_end,
+                IndexFormat::TBI_MAX_POS
+            )));
+        }
+        let n_lvls = self.index.n_lvls(max_end);
+        self.idx = unsafe {
+            htslib::hts_idx_init(0, self.fmt(), self.tell(), IndexFormat::MIN_SHIFT, n_lvls)
+        };
+        if self.idx.is_null() {
+            return Err(io::Error::other("Failed to create the index"));
+        }
+        Ok(())
+    }
+
+    /// Write a record and add it to the index, the records must be sorted.
+    fn write_record(&mut self, x: &SortRecord, contig: &str) -> io::Result<()> {
+        if self.names.last().is_none_or(|last| last != contig) {
+            self.names.push(contig.to_string());
+        }
+        self.write_line(&x.line)?;
+        let tid = ffi_int::<i32>(self.names.len() - 1, "contigs")?;
+        if unsafe { htslib::hts_idx_push(self.idx, tid, x.beg, x.end, self.tell(), 1) } < 0 {
+            return Err(io::Error::other(format!(
+                "Failed to index the record: {}",
+                x.line
+            )));
+        }
+        Ok(())
+    }
+
+    /// Close the file and write the index next to it.
+    fn finish(mut self, preset: IndexPreset) -> io::Result<()> {
+        if unsafe { htslib::hts_idx_finish(self.idx, self.tell()) } < 0 {
+            return Err(io::Error::other("Failed to finish the index"));
+        }
+
+        // same meta as tabix: the conf, then the contig names.
+        let mut meta: Vec<u8> = vec![];
+        for x in preset.conf() {
+            meta.extend_from_slice(&x.to_ne_bytes());
+        }
+        let l_nm: usize = self.names.iter().map(|x| x.len() + 1).sum();
+        meta.extend_from_slice(&ffi_int::<i32>(l_nm, "bytes of contig names")?.to_ne_bytes());
+        for x in &self.names {
+            meta.extend_from_slice(x.as_bytes());
+            meta.push(0);
+        }
+        let l_meta = ffi_int::<u32>(meta.len(), "bytes of index meta")?;
+        if unsafe { htslib::hts_idx_set_meta(self.idx, l_meta, meta.as_mut_ptr(), 1) } < 0 {
+            return Err(io::Error::other("Failed to set the index meta"));
+        }
+
+        let ret = unsafe { htslib::bgzf_close(self.fp) };
+        self.fp = ptr::null_mut();
+        if ret < 0 {
+            return Err(io::Error::other(format!(
+                "Failed to close {}",
+                self.path.display()
+            )));
+        }
+        let c_path = path_to_cstring(&self.path)?;
+        if unsafe { htslib::hts_idx_save(self.idx, c_path.as_ptr(), self.fmt()) } < 0 {
+            return Err(io::Error::other(format!(
+                "Failed to write the index of {}",
+                self.path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BgzfFile {
+    fn drop(&mut self) {
+        if !self.idx.is_null() {
+            unsafe { htslib::hts_idx_destroy(self.idx) };
+        }
+        if !self.fp.is_null() {
+            unsafe { htslib::bgzf_close(self.fp) };
+        }
+    }
+}
+
+/// `x` as the integer type of an htslib argument, an error when it does not fit.
+fn ffi_int<T: TryFrom<usize>>(x: usize, what: &str) -> io::Result<T> {
+    T::try_from(x).map_err(|_| io::Error::other(format!("Too many {} for the index: {}", what, x)))
+}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    path.to_str()
+        .and_then(|x| CString::new(x).ok())
+        .ok_or_else(|| io::Error::other(format!("Invalid path: {}", path.display())))
+}
@@ -0,0 +1,157 @@
+use rust_htslib::bam;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    error::ExcordError,
+    extractor::{ExtractorOptions, SignalExtractor},
+    region::{Chunk, RegionReader},
+    signal::SvSignal,
+};
+
+/// # Chunks written so far
+///
+/// A worker only starts a chunk less than `size` chunks ahead of the next
+/// chunk to write, so a slow chunk does not let the others fill the memory
+/// with the signals of the rest of the genome.
+struct Window {
+    size: usize,
+    /// next chunk to write, and whether the writer has stopped
+    state: Mutex<(usize, bool)>,
+    moved: Condvar,
+}
+
+impl Window {
+    /// Wait until chunk `idx` can be started, `false` if the writer has stopped.
+    fn wait(&self, idx: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && idx >= state.0 + self.size {
+            state = self.moved.wait(state).unwrap();
+        }
+        !state.1
+    }
+
+    fn advance(&self, next_out: usize) {
+        self.state.lock().unwrap().0 = next_out;
+        self.moved.notify_all();
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.moved.notify_all();
+    }
+}
+
+/// # Extract the chunks of an indexed BAM/CRAM in a worker pool
+///
+/// Each worker opens its own `IndexedReader` and takes the next chunk from a
+/// shared counter. The signals are handed to `f` in chunk order, so the result
+/// does not depend on the number of workers. At most `2 * workers` chunks are
+/// extracted or waiting ahead of the next chunk to write. Return the number
+/// of records skipped by the error policy.
+pub fn extract_parallel<F>(
+    path: &Path,
+    reference: Option<&Path>,
+    chunks: &[Chunk],
+    opts: &ExtractorOptions,
+    workers: usize,
+    mut f: F,
+) -> Result<u64, ExcordError>
+where
+    F: FnMut(SvSignal) -> Result<(), ExcordError>,
+{
+    let next_chunk = AtomicUsize::new(0);
+    let workers = workers.clamp(1, chunks.len().max(1));
+    let window = Window {
+        size: workers * 2,
+        state: Mutex::new((0, false)),
+        moved: Condvar::new(),
+    };
+    let (tx, rx) = mpsc::sync_channel::<(usize, Result<Vec<SvSignal>, ExcordError>)>(workers * 2);
+    let skipped_total = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let next_chunk = &next_chunk;
+            let skipped_total = &skipped_total;
+            let window = &window;
+            scope.spawn(move || {
+                let mut bam = match open_indexed(path, reference) {
+                    Ok(bam) => bam,
+                    Err(e) => {
+                        let _ = tx.send((0, Err(e)));
+                        return;
+                    }
+                };
+                loop {
+                    let idx = next_chunk.fetch_add(1, Ordering::SeqCst);
+                    if idx >= chunks.len() || !window.wait(idx) {
+                        break;
+                    }
+                    let mut extractor = SignalExtractor::new(
+                        RegionReader::from_chunks(bam, vec![chunks[idx]]),
+                        opts.clone(),
+                    );
+                    let signals: Result<Vec<SvSignal>, ExcordError> = extractor.by_ref().collect();
+                    let failed = signals.is_err();
+                    skipped_total.fetch_add(extractor.skipped() as usize, Ordering::SeqCst);
+                    if tx.send((idx, signals)).is_err() || failed {
+                        break;
+                    }
+                    bam = extractor.into_inner().into_inner();
+                }
+            });
+        }
+        drop(tx);
+
+        // write the chunks back in order.
+        let write = || {
+            let mut finished: BTreeMap<usize, Vec<SvSignal>> = BTreeMap::new();
+            let mut next_out = 0usize;
+            // `rx` is moved in here, returning early drops it and stops the workers.
+            for (idx, signals) in rx {
+                finished.insert(idx, signals?);
+                while let Some(signals) = finished.remove(&next_out) {
+                    signals.into_iter().try_for_each(&mut f)?;
+                    next_out += 1;
+                    window.advance(next_out);
+                }
+            }
+            Ok(next_out)
+        };
+        let written = write();
+        // release the workers waiting for a chunk that will not be written.
+        window.stop();
+        if written? < chunks.len() {
+            // a worker stopped without reporting an error.
+            return Err(ExcordError::Read {
+                after: None,
+                source: rust_htslib::errors::Error::Fetch,
+            });
+        }
+        Ok(skipped_total.load(Ordering::SeqCst) as u64)
+    })
+}
+
+fn open_indexed(path: &Path, reference: Option<&Path>) -> Result<bam::IndexedReader, ExcordError> {
+    let mut bam = bam::IndexedReader::from_path(path).map_err(|e| ExcordError::Open {
+        path: path.to_path_buf(),
+        msg: e.to_string(),
+    })?;
+    if let Some(reference) = reference {
+        bam.set_reference(reference)
+            .map_err(|e| ExcordError::Open {
+                path: reference.to_path_buf(),
+                msg: e.to_string(),
+            })?;
+    }
+    Ok(bam)
+}
@@ -0,0 +1,423 @@
+use std::{collections::HashMap, fmt, io, str::FromStr};
+
+use crate::{
+    bed_record::{Side, SignalRecord},
+    interval_tree::{Interval, IntervalTree},
+    signal::SignalType,
+    writer::check_interval,
+};
+
+/// # Kind of evidence counted by a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evidence {
+    /// Split reads
+    Split,
+    /// CIGAR deletions
+    AlignmentEvent,
+    /// CIGAR and clip insertions
+    Insertion,
+}
+
+impl Evidence {
+    /// Evidence of a signal, `None` for clips and discordant pairs.
+    pub fn of(x: SignalType) -> Option<Evidence> {
+        match x {
+            SignalType::Split => Some(Evidence::Split),
+            SignalType::CigarDel => Some(Evidence::AlignmentEvent),
+            SignalType::CigarIns
+            | SignalType::ClipInsOneSegment
+            | SignalType::ClipInsTwoSegment => Some(Evidence::Insertion),
+            SignalType::Clip | SignalType::Discordant => None,
+        }
+    }
+}
+
+/// # Region of one breakpoint of a queried SV
+///
+/// 0-based, half-open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakend {
+    pub chrom: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Breakend {
+    fn overlaps(&self, x: &Side, slop: i64) -> bool {
+        self.chrom == x.chrom
+            && x.start < self.end + slop
+            && self.start - slop < x.end.max(x.start + 1)
+    }
+}
+
+/// # SV to look up in the signals
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvQuery {
+    pub id: String,
+    pub left: Breakend,
+    pub right: Breakend,
+}
+
+impl SvQuery {
+    /// # SV of a BEDPE line
+    ///
+    /// The two regions are the first six columns, the name is the 7th column
+    /// when there is one.
+    pub fn from_bedpe(s: &str) -> Result<SvQuery, String> {
+        let fields: Vec<&str> = s.trim_end().split('\t').collect();
+        if fields.len() < 6 {
+            return Err(format!("Not a BEDPE line: {}", s));
+        }
+        let int = |x: &str| {
+            x.parse::<i64>()
+                .map_err(|_| format!("Invalid number {}: {}", x, s))
+        };
+        Ok(SvQuery {
+            id: fields.get(6).unwrap_or(&".").to_string(),
+            left: Breakend {
+                chrom: fields[0].to_string(),
+                start: int(fields[1])?,
+                end: int(fields[2])?,
+            },
+            right: Breakend {
+                chrom: fields[3].to_string(),
+                start: int(fields[4])?,
+                end: int(fields[5])?,
+            },
+        })
+    }
+
+    /// # SV of a VCF record
+    ///
+    /// The left breakpoint is `POS`, the right one the mate of a `BND` ALT,
+    /// or `END`(or `POS + |SVLEN|`) on `CHR2`(or `CHROM`). `CIPOS` and
+    /// `CIEND` widen the breakpoints.
+    pub fn from_vcf(s: &str) -> Result<SvQuery, String> {
+        let fields: Vec<&str> = s.trim_end().split('\t').collect();
+        if fields.len() < 8 {
+            return Err(format!("Not a VCF line: {}", s));
+        }
+        let pos = fields[1]
+            .parse::<i64>()
+            .map_err(|_| format!("Invalid POS: {}", s))?;
+        let info = |tag: &str| {
+            fields[7]
+                .split(';')
+                .find_map(|x| x.strip_prefix(tag)?.strip_prefix('='))
+        };
+        let int = |tag: &str| {
+            info(tag)
+                .map(|x| {
+                    x.parse::<i64>()
+                        .map_err(|_| format!("Invalid {}: {}", tag, s))
+                })
+                .transpose()
+        };
+        let ci = |tag: &str| -> Result<(i64, i64), String> {
+            let Some(x) = info(tag) else {
+                return Ok((0, 0));
+            };
+            let (a, b) = x.split_once(',').unwrap_or((x, x));
+            match (a.parse::<i64>(), b.parse::<i64>()) {
+                (Ok(a), Ok(b)) => Ok((a.min(0), b.max(0))),
+                _ => Err(format!("Invalid {}: {}", tag, s)),
+            }
+        };
+        let (mate_chrom, mate_pos) = match bnd_mate(fields[4]) {
+            Some((chrom, pos)) => (chrom, pos),
+            None => {
+                let end = match (int("END")?, int("SVLEN")?) {
+                    (Some(end), _) => end,
+                    (None, Some(len)) if info("SVTYPE") != Some("INS") => pos + len.abs(),
+                    _ => pos,
+                };
+                (info("CHR2").unwrap_or(fields[0]).to_string(), end)
+            }
+        };
+        let cipos = ci("CIPOS")?;
+        let ciend = ci("CIEND")?;
+        Ok(SvQuery {
+            id: fields[2].to_string(),
+            left: Breakend {
+                chrom: fields[0].to_string(),
+                start: (pos - 1 + cipos.0).max(0),
+                end: pos + cipos.1,
+            },
+            right: Breakend {
+                chrom: mate_chrom,
+                start: (mate_pos - 1 + ciend.0).max(0),
+                end: mate_pos + ciend.1,
+            },
+        })
+    }
+
+    /// Header line of the BEDPE-like output of [`SvQuery::write_bed`].
+    pub const HEADER: &'static str =
+        "#chrom1\tstart1\tend1\tchrom2\tstart2\tend2\tid\tsplit\talignment\tinsertion\ttotal";
+
+    /// BEDPE-like line of the SV and its support, the regions are the breakpoints without slop.
+    pub fn write_bed(&self, mut w: impl io::Write, support: &Support) -> io::Result<()> {
+        for x in [&self.left, &self.right] {
+            check_interval(&x.chrom, x.start, x.end, i64::MAX, "BED")?;
+        }
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.left.chrom,
+            self.left.start,
+            self.left.end,
+            self.right.chrom,
+            self.right.start,
+            self.right.end,
+            self.id,
+            support
+        )
+    }
+}
+
+/// Contig and 1-based position of the mate of a `BND` ALT, e.g. `N[2:70001[`.
+fn bnd_mate(alt: &str) -> Option<(String, i64)> {
+    let mut parts = alt.split(['[', ']']);
+    parts.next()?;
+    let (chrom, pos) = parts.next()?.rsplit_once(':')?;
+    Some((chrom.to_string(), pos.parse().ok()?))
+}
+
+/// # Number of signals supporting an SV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Support {
+    pub split: usize,
+    pub alignment_event: usize,
+    pub insertion: usize,
+}
+
+impl Support {
+    pub fn total(&self) -> usize {
+        self.split + self.alignment_event + self.insertion
+    }
+}
+
+impl fmt::Display for Support {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.split,
+            self.alignment_event,
+            self.insertion,
+            self.total()
+        )
+    }
+}
+
+struct Entry {
+    id: usize,
+    right: Side,
+    evidence: Evidence,
+}
+
+/// # Signals of one excord-lr output indexed by their left region
+///
+/// A signal supports an SV when one of its regions overlaps the left
+/// breakpoint and the other one the right breakpoint, both widened by the
+/// slop, like a STIX query. The left and right regions are the ones written
+/// by the BED output, e.g. the two segments of a split read or the two sides
+/// of a CIGAR deletion.
+pub struct SignalIndex {
+    trees: HashMap<String, IntervalTree<Entry>>,
+}
+
+impl SignalIndex {
+    pub fn new(records: impl IntoIterator<Item = SignalRecord>) -> SignalIndex {
+        let mut intervals: HashMap<String, Vec<Interval<Entry>>> = HashMap::new();
+        for (id, x) in records.into_iter().enumerate() {
+            let Some(evidence) = Evidence::of(x.signal) else {
+                continue;
+            };
+            intervals
+                .entry(x.left.chrom.clone())
+                .or_default()
+                .push(Interval {
+                    start: x.left.start,
+                    end: x.left.end,
+                    value: Entry {
+                        id,
+                        right: x.right,
+                        evidence,
+                    },
+                });
+        }
+        SignalIndex {
+            trees: intervals
+                .into_iter()
+                .map(|(chrom, x)| (chrom, IntervalTree::new(x)))
+                .collect(),
+        }
+    }
+
+    /// Number of indexed signals.
+    pub fn len(&self) -> usize {
+        self.trees.values().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Count the signals supporting `sv`, in both orientations of the breakpoints.
+    pub fn count(&self, sv: &SvQuery, slop: i64) -> Support {
+        let mut hits: Vec<(usize, Evidence)> = vec![];
+        for (a, b) in [(&sv.left, &sv.right), (&sv.right, &sv.left)] {
+            let Some(tree) = self.trees.get(&a.chrom) else {
+                continue;
+            };
+            hits.extend(
+                tree.find(a.start - slop, a.end + slop)
+                    .into_iter()
+                    .filter(|x| b.overlaps(&x.value.right, slop))
+                    .map(|x| (x.value.id, x.value.evidence)),
+            );
+        }
+        hits.sort_unstable_by_key(|x| x.0);
+        hits.dedup_by_key(|x| x.0);
+        let mut support = Support::default();
+        for (_, evidence) in hits {
+            match evidence {
+                Evidence::Split => support.split += 1,
+                Evidence::AlignmentEvent => support.alignment_event += 1,
+                Evidence::Insertion => support.insertion += 1,
+            }
+        }
+        support
+    }
+}
+
+/// # Format of the SVs to query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvFormat {
+    Vcf,
+    Bedpe,
+}
+
+impl SvFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SvFormat::Vcf => "vcf",
+            SvFormat::Bedpe => "bedpe",
+        }
+    }
+
+    pub fn parse_line(&self, s: &str) -> Result<SvQuery, String> {
+        match self {
+            SvFormat::Vcf => SvQuery::from_vcf(s),
+            SvFormat::Bedpe => SvQuery::from_bedpe(s),
+        }
+    }
+}
+
+impl fmt::Display for SvFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SvFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [SvFormat::Vcf, SvFormat::Bedpe]
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| format!("Unknown SV format: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vcf(pos: i64, alt: &str, info: &str) -> SvQuery {
+        SvQuery::from_vcf(&format!("1\t{}\tsv1\tN\t{}\t.\tPASS\t{}", pos, alt, info)).unwrap()
+    }
+
+    fn breakend(chrom: &str, start: i64, end: i64) -> Breakend {
+        Breakend {
+            chrom: chrom.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn parses_vcf_breakpoints() {
+        let x = vcf(1001, "<DEL>", "SVTYPE=DEL;END=5000");
+        assert_eq!(x.id, "sv1");
+        assert_eq!(
+            (x.left, x.right),
+            (breakend("1", 1000, 1001), breakend("1", 4999, 5000))
+        );
+        let x = vcf(1001, "<DEL>", "SVTYPE=DEL;SVLEN=-3000");
+        assert_eq!(x.right, breakend("1", 4000, 4001));
+        // the length of an insertion is not on the reference.
+        let x = vcf(1001, "<INS>", "SVTYPE=INS;SVLEN=3000");
+        assert_eq!(x.right, breakend("1", 1000, 1001));
+        let x = vcf(1001, "N[2:70001[", "SVTYPE=BND");
+        assert_eq!(x.right, breakend("2", 70000, 70001));
+        let x = vcf(1001, "<TRA>", "SVTYPE=TRA;CHR2=5;END=300");
+        assert_eq!(x.right, breakend("5", 299, 300));
+    }
+
+    #[test]
+    fn confidence_intervals_widen_the_breakpoints() {
+        let x = vcf(1001, "<DEL>", "END=5000;CIPOS=-10,20;CIEND=-5,5");
+        assert_eq!(x.left, breakend("1", 990, 1021));
+        assert_eq!(x.right, breakend("1", 4994, 5005));
+        // stopped at the start of the contig, one value only widens one side.
+        let x = vcf(11, "<DEL>", "END=5000;CIPOS=-50,50");
+        assert_eq!(x.left, breakend("1", 0, 61));
+        let x = vcf(11, "<DEL>", "END=5000;CIPOS=50");
+        assert_eq!(x.left, breakend("1", 10, 61));
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(SvQuery::from_vcf("1\t1001\tsv1\tN\t<DEL>\t.\tPASS").is_err());
+        assert!(SvQuery::from_vcf("1\tx\tsv1\tN\t<DEL>\t.\tPASS\tEND=5").is_err());
+        assert!(SvQuery::from_vcf("1\t1001\tsv1\tN\t<DEL>\t.\tPASS\tEND=a").is_err());
+        assert!(SvQuery::from_vcf("1\t1001\tsv1\tN\t<DEL>\t.\tPASS\tCIPOS=a,1").is_err());
+        assert!(SvQuery::from_bedpe("1\t100\t200\t1\t500").is_err());
+        assert!(SvQuery::from_bedpe("1\t100\t200\t1\t500\tx").is_err());
+    }
+
+    #[test]
+    fn parses_bedpe_lines() {
+        let x = SvQuery::from_bedpe("1\t100\t200\t2\t500\t600\tdel1\textra").unwrap();
+        assert_eq!(x.id, "del1");
+        assert_eq!(
+            (x.left, x.right),
+            (breakend("1", 100, 200), breakend("2", 500, 600))
+        );
+        assert_eq!(SvQuery::from_bedpe("1\t1\t2\t1\t3\t4").unwrap().id, ".");
+    }
+
+    #[test]
+    fn counts_signals_in_both_orientations() {
+        let records = [
+            "1\t900\t1000\t1\t1\t5000\t5100\t1\t1\tsplit\tr1",
+            "2\t5000\t5100\t1\t1\t900\t1000\t1\t1\tsplit\tr2",
+            "1\t995\t995\t1\t1\t5005\t5005\t1\t1\tcigar-del\tr3",
+            "1\t990\t1000\t1\t1\t1000\t1000\t1\t1\tclip\tr4",
+            "1\t900\t1000\t1\t1\t9000\t9100\t1\t1\tsplit\tr5",
+        ]
+        .map(|x| x.parse::<SignalRecord>().unwrap());
+        let index = SignalIndex::new(records);
+        // the clip is not indexed.
+        assert_eq!(index.len(), 4);
+        let sv = SvQuery::from_bedpe("1\t1000\t1001\t1\t5000\t5001\tdel").unwrap();
+        let support = index.count(&sv, 10);
+        assert_eq!((support.split, support.alignment_event), (1, 1));
+        let sv = SvQuery::from_bedpe("1\t5000\t5001\t1\t1000\t1001\tdel").unwrap();
+        assert_eq!(index.count(&sv, 10).total(), 2);
+        assert_eq!(index.count(&sv, 0).total(), 0);
+    }
+}
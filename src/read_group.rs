@@ -0,0 +1,85 @@
+use rust_htslib::bam::HeaderView;
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// # Samples of the read groups of a BAM/CRAM header
+///
+/// Maps the `ID` of each `@RG` line to its `SM`. Read groups without `SM`
+/// have no sample.
+#[derive(Debug, Clone, Default)]
+pub struct ReadGroups {
+    /// `ID` in the order of the header
+    ids: Vec<String>,
+    samples: HashMap<String, String>,
+}
+
+impl ReadGroups {
+    pub fn from_header(header: &HeaderView) -> ReadGroups {
+        let text = String::from_utf8_lossy(header.as_bytes());
+        let mut ids = vec![];
+        let mut samples = HashMap::new();
+        for line in text.lines().filter(|x| x.starts_with("@RG\t")) {
+            let field = |tag: &str| {
+                line.split('\t')
+                    .find_map(|x| x.strip_prefix(tag))
+                    .map(|x| x.to_string())
+            };
+            let Some(id) = field("ID:") else {
+                continue;
+            };
+            if let Some(sm) = field("SM:") {
+                samples.insert(id.clone(), sm);
+            }
+            ids.push(id);
+        }
+        ReadGroups { ids, samples }
+    }
+
+    /// `ID` of the read groups in the order of the header.
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    /// Sample of a read group, `None` if the read group has no `SM` or is not in the header.
+    pub fn sample(&self, id: &str) -> Option<&str> {
+        self.samples.get(id).map(|x| x.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// # How the output is split into one file per group of reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplitBy {
+    /// One file per `SM`
+    Sample,
+    /// One file per `RG`
+    ReadGroup,
+}
+
+impl SplitBy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SplitBy::Sample => "sample",
+            SplitBy::ReadGroup => "read-group",
+        }
+    }
+}
+
+impl fmt::Display for SplitBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SplitBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [SplitBy::Sample, SplitBy::ReadGroup]
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| format!("Unknown split: {}", s))
+    }
+}
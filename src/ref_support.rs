@@ -0,0 +1,319 @@
+use rust_htslib::{
+    bam::{self, HeaderView, Read, Record},
+    errors::Result as HtsResult,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+use crate::{
+    extractor::{extract_record, ExtractorOptions},
+    read_group::ReadGroups,
+    signal::{SignalType, SplitType, SvSignal},
+};
+
+/// Signal type and SV type of split reads and discordant pairs, only the
+/// signals of the same kind make a read alternate.
+type Kind = (SignalType, Option<SplitType>);
+
+/// Largest span of the breakpoints fetched together.
+const MAX_LOCUS: i64 = 10_000;
+
+/// # Reads of the reference and the alternate allele at the breakpoints of a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AlleleCounts {
+    /// Reads spanning a breakpoint without any signal at it
+    pub ref_reads: usize,
+    /// Reads with a signal at a breakpoint, the read of the signal included
+    pub alt_reads: usize,
+}
+
+impl AlleleCounts {
+    /// Variant allele fraction, `None` without reads.
+    pub fn vaf(&self) -> Option<f64> {
+        let total = self.ref_reads + self.alt_reads;
+        (total > 0).then(|| self.alt_reads as f64 / total as f64)
+    }
+}
+
+/// Tab-separated `ref`, `alt` and `vaf`, `.` for an unknown fraction.
+impl fmt::Display for AlleleCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t", self.ref_reads, self.alt_reads)?;
+        match self.vaf() {
+            Some(x) => write!(f, "{:.3}", x),
+            None => f.write_str("."),
+        }
+    }
+}
+
+/// # Options of the reference support counting
+#[derive(Debug, Clone)]
+pub struct RefSupportOptions {
+    /// Minimal number of aligned bases on both sides of a breakpoint for a read to span it
+    pub flank: i64,
+    /// Maximal distance of a signal to a breakpoint to count its read as alternate
+    pub slop: i64,
+}
+
+impl Default for RefSupportOptions {
+    fn default() -> Self {
+        RefSupportOptions {
+            flank: 20,
+            slop: 50,
+        }
+    }
+}
+
+/// # Reads around close breakpoints, fetched once
+struct Locus {
+    tid: u32,
+    start: i64,
+    end: i64,
+    /// aligned span and name of the selected records
+    records: Vec<(i64, i64, String)>,
+    /// breakpoints on this contig of the signals of these records, and their read
+    signals: Vec<(Kind, i64, String)>,
+}
+
+/// # Reference support of the signals of one input
+///
+/// The signals are given in the order of the extraction and counted once
+/// the extraction is past their first breakpoint after the record by more
+/// than `max(flank, slop)`, so the signals of the other reads at it are
+/// counted in the same batch. The breakpoints of a batch closer than twice
+/// that distance are grouped into one locus, fetched once from the indexed
+/// input with the MapQ and flag filters of the extraction.
+///
+/// A read of a locus is alternate for a signal when one of its signals, as
+/// extracted again from the record, has the same type, the same SV type for
+/// split reads and discordant pairs, and a breakpoint within `slop` of a
+/// breakpoint of the signal. The records spanning a breakpoint by `flank` on
+/// both sides are reference reads unless they have a signal of any kind
+/// within `slop` of it. A signal with two
+/// breakpoints, e.g. a deletion, counts the distinct reads of both.
+pub struct RefSupport<'a, T> {
+    reader: bam::IndexedReader,
+    header: HeaderView,
+    read_groups: ReadGroups,
+    opts: ExtractorOptions,
+    ref_opts: RefSupportOptions,
+    contigs: &'a [String],
+    /// tid of each contig, by its name in the output
+    tids: HashMap<&'a str, u32>,
+    /// signals not counted yet, with the position the extraction must pass
+    pending: Vec<((i32, i64), SvSignal, T)>,
+    /// position of the extraction at the last batch
+    position: (i32, i64),
+    record: Record,
+    extracted: VecDeque<SvSignal>,
+}
+
+impl<'a, T> RefSupport<'a, T> {
+    /// `contigs` are the names of the contigs in the output, in the order of the header.
+    pub fn new(
+        reader: bam::IndexedReader,
+        contigs: &'a [String],
+        opts: ExtractorOptions,
+        ref_opts: RefSupportOptions,
+    ) -> RefSupport<'a, T> {
+        let header = reader.header().clone();
+        RefSupport {
+            read_groups: ReadGroups::from_header(&header),
+            header,
+            reader,
+            opts,
+            ref_opts,
+            contigs,
+            tids: contigs
+                .iter()
+                .enumerate()
+                .map(|(tid, x)| (x.as_str(), tid as u32))
+                .collect(),
+            pending: vec![],
+            position: (-1, 0),
+            record: Record::new(),
+            extracted: VecDeque::new(),
+        }
+    }
+
+    /// Distance of a breakpoint to the reads fetched for it.
+    fn pad(&self) -> i64 {
+        self.ref_opts.flank.max(self.ref_opts.slop)
+    }
+
+    /// Add the next signal of the extraction with `extra`, the signals
+    /// counted are appended to `out` with their counts.
+    pub fn push(
+        &mut self,
+        x: SvSignal,
+        extra: T,
+        out: &mut Vec<(SvSignal, T, AlleleCounts)>,
+    ) -> HtsResult<()> {
+        let (tid, pos) = (x.read().tid, x.read().pos);
+        // the other reads with a signal at a breakpoint start before it.
+        let anchor = x
+            .breakpoints()
+            .iter()
+            .filter(|(chrom, bp)| {
+                self.tids.get(chrom).map(|x| *x as i32) == Some(tid) && *bp >= pos
+            })
+            .map(|x| x.1)
+            .min()
+            .unwrap_or(pos);
+        let pad = self.pad();
+        self.pending.push(((tid, anchor + pad), x, extra));
+        if tid != self.position.0 || pos > self.position.1 + pad {
+            self.position = (tid, pos);
+            self.flush((tid, pos), out)?;
+        }
+        Ok(())
+    }
+
+    /// Count the remaining signals.
+    pub fn finish(mut self, out: &mut Vec<(SvSignal, T, AlleleCounts)>) -> HtsResult<()> {
+        self.flush((i32::MAX, i64::MAX), out)
+    }
+
+    /// Count the signals to pass before `position`.
+    fn flush(
+        &mut self,
+        position: (i32, i64),
+        out: &mut Vec<(SvSignal, T, AlleleCounts)>,
+    ) -> HtsResult<()> {
+        let (batch, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|x| x.0 < position);
+        self.pending = pending;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let pad = self.pad();
+        let mut points: Vec<(u32, i64)> = batch
+            .iter()
+            .flat_map(|x| x.1.breakpoints())
+            .filter_map(|(chrom, pos)| Some((*self.tids.get(chrom)?, pos)))
+            .collect();
+        points.sort_unstable();
+        let mut loci: Vec<Locus> = vec![];
+        for (tid, pos) in points {
+            match loci.last_mut() {
+                Some(x)
+                    if x.tid == tid && pos - pad <= x.end && pos + pad - x.start <= MAX_LOCUS =>
+                {
+                    x.end = pos + pad
+                }
+                _ => loci.push(Locus {
+                    tid,
+                    start: (pos - pad).max(0),
+                    end: pos + pad,
+                    records: vec![],
+                    signals: vec![],
+                }),
+            }
+        }
+        for x in loci.iter_mut() {
+            self.load(x)?;
+        }
+        for (_, x, extra) in batch {
+            let counts = self.count(&x, &loci);
+            out.push((x, extra, counts));
+        }
+        Ok(())
+    }
+
+    /// Read the records of a locus and their signals.
+    fn load(&mut self, locus: &mut Locus) -> HtsResult<()> {
+        let chrom = self.contigs[locus.tid as usize].as_str();
+        // a clip breakpoint is the end of its record, one past its last base.
+        self.reader
+            .fetch((locus.tid, (locus.start - 1).max(0), locus.end + 1))?;
+        while let Some(result) = self.reader.read(&mut self.record) {
+            result?;
+            let record = &self.record;
+            if record.is_unmapped() || !self.opts.is_selected(record) {
+                continue;
+            }
+            let qname = String::from_utf8_lossy(record.qname()).into_owned();
+            // an invalid record was already reported by the extraction.
+            if extract_record(
+                record,
+                &self.header,
+                &self.read_groups,
+                &self.opts,
+                &mut self.extracted,
+            )
+            .is_ok()
+            {
+                for x in &self.extracted {
+                    let kind = (x.signal_type(), x.split_type());
+                    for (c, pos) in x.breakpoints() {
+                        if c == chrom {
+                            locus.signals.push((kind, pos, qname.clone()));
+                        }
+                    }
+                }
+            }
+            self.extracted.clear();
+            locus
+                .records
+                .push((record.pos(), record.cigar().end_pos(), qname));
+        }
+        Ok(())
+    }
+
+    /// Reads of the reference and the alternate allele at the breakpoints of `x`.
+    fn count(&self, x: &SvSignal, loci: &[Locus]) -> AlleleCounts {
+        let kind = (x.signal_type(), x.split_type());
+        let (flank, slop) = (self.ref_opts.flank, self.ref_opts.slop);
+        let breakpoints: Vec<(&Locus, i64)> = x
+            .breakpoints()
+            .into_iter()
+            .filter_map(|(chrom, pos)| {
+                let tid = *self.tids.get(chrom)?;
+                let i = loci.partition_point(|x| (x.tid, x.end) < (tid, pos));
+                loci.get(i)
+                    .filter(|x| x.tid == tid && x.start <= pos)
+                    .map(|x| (x, pos))
+            })
+            .collect();
+
+        let mut alt: HashSet<&str> = HashSet::from([x.read().qname.as_str()]);
+        for (locus, pos) in &breakpoints {
+            alt.extend(
+                locus
+                    .signals
+                    .iter()
+                    .filter(|x| x.0 == kind && (x.1 - pos).abs() <= slop)
+                    .map(|x| x.2.as_str()),
+            );
+        }
+        // a read with a signal of another kind is neither alternate nor reference.
+        let mut with_signal: HashSet<&str> = HashSet::new();
+        for (locus, pos) in &breakpoints {
+            with_signal.extend(
+                locus
+                    .signals
+                    .iter()
+                    .filter(|x| (x.1 - pos).abs() <= slop)
+                    .map(|x| x.2.as_str()),
+            );
+        }
+        let mut reference: HashSet<&str> = HashSet::new();
+        for (locus, pos) in &breakpoints {
+            reference.extend(
+                locus
+                    .records
+                    .iter()
+                    .filter(|x| x.0 <= pos - flank && x.1 >= pos + flank)
+                    .map(|x| x.2.as_str())
+                    .filter(|x| !alt.contains(x) && !with_signal.contains(x)),
+            );
+        }
+        AlleleCounts {
+            ref_reads: reference.len(),
+            alt_reads: alt.len(),
+        }
+    }
+}
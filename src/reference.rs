@@ -0,0 +1,273 @@
+use rust_htslib::{
+    bam::{HeaderView, Read},
+    htslib,
+};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// # Format of an opened alignment file
+///
+/// Sniffed by htslib from the magic bytes of the content (`CRAM`, `BAM\1` in
+/// the first BGZF block, text otherwise), never from the file name, so it also
+/// works for the standard input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Bam,
+    Sam,
+    Cram,
+}
+
+impl InputFormat {
+    /// Format of an opened reader, `None` for other formats htslib can open.
+    pub fn of<R: Read>(reader: &R) -> Option<InputFormat> {
+        let format = unsafe { (*reader.htsfile()).format.format };
+        match format {
+            htslib::htsExactFormat_bam => Some(InputFormat::Bam),
+            htslib::htsExactFormat_sam => Some(InputFormat::Sam),
+            htslib::htsExactFormat_cram => Some(InputFormat::Cram),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InputFormat::Bam => "BAM",
+            InputFormat::Sam => "SAM",
+            InputFormat::Cram => "CRAM",
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// # Contig of an `@SQ` header line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqLine {
+    pub name: String,
+    pub len: u64,
+    /// MD5 of the sequence(`M5`), lower case
+    pub m5: Option<String>,
+    /// Location of the sequence(`UR`), a path or an URL
+    pub ur: Option<String>,
+}
+
+/// `@SQ` lines of a header, in order.
+pub fn header_contigs(header: &HeaderView) -> Vec<SqLine> {
+    let text = String::from_utf8_lossy(header.as_bytes());
+    let mut contigs = vec![];
+    for line in text.lines().filter(|x| x.starts_with("@SQ\t")) {
+        let field = |tag: &str| {
+            line.split('\t')
+                .find_map(|x| x.strip_prefix(tag))
+                .map(|x| x.to_string())
+        };
+        let Some(name) = field("SN:") else {
+            continue;
+        };
+        contigs.push(SqLine {
+            name,
+            len: field("LN:").and_then(|x| x.parse().ok()).unwrap_or(0),
+            m5: field("M5:").map(|x| x.to_ascii_lowercase()),
+            ur: field("UR:"),
+        });
+    }
+    contigs
+}
+
+/// # Names and lengths of the sequences of a reference FASTA
+///
+/// Read from the `.fai` next to it when there is one, otherwise the FASTA is
+/// read through. A compressed FASTA needs its `.fai`.
+pub fn fasta_contigs(path: &Path) -> Result<Vec<(String, u64)>, String> {
+    let mut fai = path.as_os_str().to_owned();
+    fai.push(".fai");
+    let fai = PathBuf::from(fai);
+    if fai.is_file() {
+        let f = File::open(&fai).map_err(|e| e.to_string())?;
+        let mut contigs = vec![];
+        for line in BufReader::new(f).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            let mut fields = line.split('\t');
+            let (Some(name), Some(len)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let len = len
+                .parse()
+                .map_err(|_| format!("Invalid length in {}: {}", fai.display(), line))?;
+            contigs.push((name.to_string(), len));
+        }
+        return Ok(contigs);
+    }
+    if path.extension().is_some_and(|x| x == "gz") {
+        return Err(format!("{} is compressed and has no .fai", path.display()));
+    }
+
+    let f = File::open(path).map_err(|e| e.to_string())?;
+    let mut contigs: Vec<(String, u64)> = vec![];
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if let Some(name) = line.strip_prefix('>') {
+            let name = name.split_whitespace().next().unwrap_or_default();
+            contigs.push((name.to_string(), 0));
+        } else if let Some((_, len)) = contigs.last_mut() {
+            *len += line.trim_end().len() as u64;
+        } else if !line.trim().is_empty() {
+            return Err("not a FASTA file".to_string());
+        }
+    }
+    Ok(contigs)
+}
+
+/// # Check that a reference matches the header
+///
+/// Every `@SQ` contig must be in the reference with the same length, the
+/// reference may have more contigs. The error names the first mismatch.
+pub fn check_contigs(header: &[SqLine], reference: &[(String, u64)]) -> Result<(), String> {
+    let reference: HashMap<&str, u64> = reference.iter().map(|(n, l)| (n.as_str(), *l)).collect();
+    for sq in header {
+        match reference.get(sq.name.as_str()) {
+            None => {
+                return Err(format!(
+                    "contig {} of the header is not in the reference",
+                    sq.name
+                ))
+            }
+            Some(len) if *len != sq.len => {
+                return Err(format!(
+                    "contig {} has {} bp in the header but {} bp in the reference",
+                    sq.name, sq.len, len
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// # Local reference sequences looked up by MD5
+///
+/// The same lookup as htslib, from the `REF_CACHE` and `REF_PATH`
+/// environment variables. Both are templates where `%s` is replaced by the
+/// rest of the MD5 and `%Ns` by its next `N` characters, e.g.
+/// `$HOME/.cache/hts-ref/%2s/%2s/%s`. `REF_PATH` is a `:` separated list, an
+/// entry without `%` is a directory holding files named by their MD5.
+#[derive(Debug, Clone, Default)]
+pub struct RefPath {
+    templates: Vec<String>,
+    /// `REF_PATH` has an URL, htslib may download the sequences
+    remote: bool,
+}
+
+impl RefPath {
+    pub fn from_env() -> RefPath {
+        RefPath::new(
+            env::var("REF_CACHE").ok().as_deref(),
+            env::var("REF_PATH").ok().as_deref(),
+        )
+    }
+
+    pub fn new(ref_cache: Option<&str>, ref_path: Option<&str>) -> RefPath {
+        let mut templates: Vec<String> = ref_cache
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .into_iter()
+            .collect();
+        let mut remote = false;
+        for entry in split_ref_path(ref_path.unwrap_or_default()) {
+            if entry.contains("://") {
+                remote = true;
+            } else if entry.contains('%') {
+                templates.push(entry);
+            } else {
+                templates.push(format!("{}/%s", entry.trim_end_matches('/')));
+            }
+        }
+        RefPath { templates, remote }
+    }
+
+    /// Return `true` if `REF_PATH` has an URL, the sequences not found locally may be downloaded.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Local file holding the sequence with this MD5.
+    pub fn find(&self, m5: &str) -> Option<PathBuf> {
+        self.templates
+            .iter()
+            .map(|x| PathBuf::from(expand_md5(x, m5)))
+            .find(|x| x.is_file())
+    }
+
+    /// First contig whose sequence can not be found, from its `M5` or a local `UR`.
+    pub fn first_missing<'a>(&self, header: &'a [SqLine]) -> Option<&'a SqLine> {
+        header.iter().find(|sq| {
+            let local = sq
+                .ur
+                .as_deref()
+                .map(|x| x.strip_prefix("file://").unwrap_or(x))
+                .is_some_and(|x| Path::new(x).is_file());
+            let cached = sq.m5.as_deref().is_some_and(|x| self.find(x).is_some());
+            let remote = self.remote && sq.m5.is_some();
+            !(local || cached || remote)
+        })
+    }
+}
+
+/// Split `REF_PATH` on `:`, except after the scheme of an URL.
+fn split_ref_path(s: &str) -> Vec<String> {
+    let mut entries: Vec<String> = vec![];
+    for part in s.split(':') {
+        match entries.last_mut() {
+            Some(last)
+                if part.starts_with("//") && ["http", "https", "ftp"].contains(&last.as_str()) =>
+            {
+                last.push(':');
+                last.push_str(part);
+            }
+            _ => entries.push(part.to_string()),
+        }
+    }
+    entries.retain(|x| !x.is_empty());
+    entries
+}
+
+/// Replace `%s` and `%Ns` of a template by the MD5.
+fn expand_md5(template: &str, m5: &str) -> String {
+    let mut out = String::new();
+    let mut rest = m5;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut n = String::new();
+        while let Some(d) = chars.peek().filter(|x| x.is_ascii_digit()) {
+            n.push(*d);
+            chars.next();
+        }
+        match chars.next() {
+            Some('s') => {
+                let n = n.parse::<usize>().unwrap_or(rest.len()).min(rest.len());
+                out.push_str(&rest[..n]);
+                rest = &rest[n..];
+            }
+            Some(x) => {
+                out.push('%');
+                out.push_str(&n);
+                out.push(x);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
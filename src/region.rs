@@ -0,0 +1,368 @@
+use rust_htslib::{
+    bam::{self, HeaderView, Read, Record},
+    errors::Result as HtsResult,
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{extractor::RecordSource, utils::contig_len};
+
+/// # Genomic interval requested by the user
+///
+/// 0-based, half-open. `end` is `None` for the rest of the contig.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub contig: String,
+    pub start: i64,
+    pub end: Option<i64>,
+    /// The text of a region with `:`, which may also be the name of a contig
+    /// (`HLA-A*01:01:01:01`), see [`resolve_regions`]
+    pub text: Option<String>,
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Parse a samtools style region: `chr`, `chr:start` or `chr:start-end`.
+    /// The coordinates are 1-based and inclusive, `,` in numbers is allowed.
+    ///
+    /// A text with `:` whose range does not parse is kept whole as the
+    /// contig, [`resolve_regions`] fails on it unless the header has a
+    /// contig of that name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.starts_with(':') {
+            return Err(format!("Invalid region: {}", s));
+        }
+        let (contig, range) = match s.rsplit_once(':') {
+            Some((contig, range)) if !range.is_empty() => (contig, range),
+            // `chr1:` is the whole contig.
+            Some((contig, _)) => (contig, ""),
+            None => (s, ""),
+        };
+        if range.is_empty() {
+            return Ok(Region {
+                contig: contig.to_string(),
+                start: 0,
+                end: None,
+                text: (contig != s).then(|| s.to_string()),
+            });
+        }
+        let parse_pos = |x: &str| x.replace(',', "").parse::<i64>().ok();
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_pos(start).map(|x| x - 1), parse_pos(end)),
+            None => (parse_pos(range).map(|x| x - 1), None),
+        };
+        let valid = match (start, end) {
+            (Some(start), end) => start >= 0 && end.is_none_or(|end| end > start),
+            (None, _) => false,
+        };
+        Ok(Region {
+            contig: if valid { contig } else { s }.to_string(),
+            start: start.filter(|_| valid).unwrap_or(0),
+            end: end.filter(|_| valid),
+            text: Some(s.to_string()),
+        })
+    }
+}
+
+/// # Read the regions of a BED file
+///
+/// Only the first three columns are used, `track`, `browser` and `#` lines are skipped.
+pub fn read_regions_bed(path: impl AsRef<Path>) -> Result<Vec<Region>, String> {
+    let path = path.as_ref();
+    let f = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut regions = vec![];
+    for (n, line) in BufReader::new(f).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let invalid = || format!("{}:{}: invalid BED line", path.display(), n + 1);
+        if fields.len() < 3 {
+            return Err(invalid());
+        }
+        let start = fields[1].parse::<i64>().map_err(|_| invalid())?;
+        let end = fields[2].parse::<i64>().map_err(|_| invalid())?;
+        if start < 0 || end <= start {
+            return Err(invalid());
+        }
+        regions.push(Region {
+            contig: fields[0].to_string(),
+            start,
+            end: Some(end),
+            text: None,
+        });
+    }
+    Ok(regions)
+}
+
+/// # Resolve the regions against the header
+///
+/// Return `(tid, start, end)` sorted by coordinate, overlapping and adjacent
+/// regions are merged so that each interval is fetched only once. As in
+/// samtools, a region with `:` is a whole contig if the header has a contig
+/// of that name, otherwise the text after the last `:` is its range.
+pub fn resolve_regions(
+    regions: &[Region],
+    header: &HeaderView,
+) -> Result<Vec<(u32, i64, i64)>, String> {
+    let mut resolved = vec![];
+    for r in regions {
+        let whole = r.text.as_ref().and_then(|x| header.tid(x.as_bytes()));
+        let (tid, start, end) = match (whole, &r.text) {
+            (Some(tid), _) => (tid, 0, None),
+            (None, Some(text)) if r.contig == *text => {
+                return Err(format!(
+                    "Invalid region: {}, nor a contig of the BAM header",
+                    text
+                ))
+            }
+            _ => (
+                header
+                    .tid(r.contig.as_bytes())
+                    .ok_or_else(|| format!("Contig {} is not in the BAM header", r.contig))?,
+                r.start,
+                r.end,
+            ),
+        };
+        let len = contig_len(header, tid);
+        let end = end.unwrap_or(len).min(len);
+        if start < end {
+            resolved.push((tid, start, end));
+        }
+    }
+    resolved.sort();
+
+    let mut merged: Vec<(u32, i64, i64)> = vec![];
+    for x in resolved {
+        match merged.last_mut() {
+            Some(last) if last.0 == x.0 && x.1 <= last.2 => last.2 = last.2.max(x.2),
+            _ => merged.push(x),
+        }
+    }
+    Ok(merged)
+}
+
+/// # Every contig of the header as one region
+pub fn whole_genome(header: &HeaderView) -> Vec<(u32, i64, i64)> {
+    (0..header.target_count())
+        .map(|tid| (tid, 0, contig_len(header, tid)))
+        .collect()
+}
+
+/// # Part of a region fetched by one reader
+///
+/// `[start, end)` is fetched through the index, only the records starting at
+/// or after `own_start` belong to the chunk. Every record is therefore owned by
+/// exactly one chunk even if it overlaps several of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub tid: u32,
+    pub start: i64,
+    pub end: i64,
+    pub own_start: i64,
+}
+
+/// # Split sorted and merged regions into chunks
+///
+/// Each region is cut into pieces of at most `chunk_size` bp (`None` keeps the
+/// regions as they are). The first chunk of a region owns the records
+/// starting before it, unless they belong to the previous region on the same contig.
+pub fn to_chunks(regions: &[(u32, i64, i64)], chunk_size: Option<i64>) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut prev: Option<(u32, i64)> = None;
+    for &(tid, start, end) in regions {
+        let mut own_start = match prev {
+            Some((prev_tid, prev_end)) if prev_tid == tid => prev_end,
+            _ => i64::MIN,
+        };
+        let step = chunk_size.unwrap_or(end - start).max(1);
+        let mut chunk_start = start;
+        loop {
+            let chunk_end = (chunk_start + step).min(end);
+            chunks.push(Chunk {
+                tid,
+                start: chunk_start,
+                end: chunk_end,
+                own_start,
+            });
+            if chunk_end >= end {
+                break;
+            }
+            chunk_start = chunk_end;
+            own_start = chunk_end;
+        }
+        prev = Some((tid, end));
+    }
+    chunks
+}
+
+/// # Reader restricted to a list of regions
+///
+/// Fetch each chunk through the index in turn. A record overlapping several
+/// regions is only returned for the first one.
+pub struct RegionReader {
+    reader: bam::IndexedReader,
+    chunks: Vec<Chunk>,
+    next_chunk: usize,
+    own_start: i64,
+    fetched: bool,
+}
+
+impl RegionReader {
+    pub fn new(reader: bam::IndexedReader, regions: &[Region]) -> Result<RegionReader, String> {
+        let regions = resolve_regions(regions, reader.header())?;
+        Ok(RegionReader::from_chunks(reader, to_chunks(&regions, None)))
+    }
+
+    pub fn from_chunks(reader: bam::IndexedReader, chunks: Vec<Chunk>) -> RegionReader {
+        RegionReader {
+            reader,
+            chunks,
+            next_chunk: 0,
+            own_start: i64::MIN,
+            fetched: false,
+        }
+    }
+
+    /// Give the underlying reader back.
+    pub fn into_inner(self) -> bam::IndexedReader {
+        self.reader
+    }
+
+    /// Fetch the next chunk, return `false` when all chunks are done.
+    fn fetch_next(&mut self) -> HtsResult<bool> {
+        let Some(&chunk) = self.chunks.get(self.next_chunk) else {
+            return Ok(false);
+        };
+        self.reader.fetch((chunk.tid, chunk.start, chunk.end))?;
+        self.own_start = chunk.own_start;
+        self.next_chunk += 1;
+        self.fetched = true;
+        Ok(true)
+    }
+}
+
+impl RecordSource for RegionReader {
+    fn read_record(&mut self, record: &mut Record) -> Option<HtsResult<()>> {
+        loop {
+            if !self.fetched {
+                match self.fetch_next() {
+                    Ok(true) => {}
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            match self.reader.read(record) {
+                // a record starting before the chunk has been returned for the previous one.
+                Some(Ok(())) if record.pos() < self.own_start => continue,
+                None => self.fetched = false,
+                x => return x,
+            }
+        }
+    }
+
+    fn header_view(&self) -> &HeaderView {
+        self.reader.header()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::{header::HeaderRecord, Header};
+
+    fn header(contigs: &[(&str, u64)]) -> HeaderView {
+        let mut header = Header::new();
+        for (name, len) in contigs {
+            header.push_record(
+                HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", name)
+                    .push_tag(b"LN", len),
+            );
+        }
+        HeaderView::from_header(&header)
+    }
+
+    fn region(s: &str) -> Region {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_samtools_regions() {
+        let r = region("chr1:1,001-2,000");
+        assert_eq!(
+            (r.contig.as_str(), r.start, r.end),
+            ("chr1", 1000, Some(2000))
+        );
+        let r = region("chr1:1001");
+        assert_eq!((r.contig.as_str(), r.start, r.end), ("chr1", 1000, None));
+        let r = region("chr1");
+        assert_eq!(
+            (r.contig.as_str(), r.start, r.end, r.text),
+            ("chr1", 0, None, None)
+        );
+        assert!("".parse::<Region>().is_err());
+        assert!(":1-10".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn resolves_and_merges_regions() {
+        let header = header(&[("chr1", 10_000), ("chr2", 5_000)]);
+        let regions = [
+            region("chr2:101-200"),
+            region("chr1:1001-2000"),
+            region("chr1:2001-3000"),
+            region("chr2"),
+            region("chr1:9001-20000"),
+        ];
+        assert_eq!(
+            resolve_regions(&regions, &header).unwrap(),
+            [(0, 1000, 3000), (0, 9000, 10_000), (1, 0, 5000)]
+        );
+        assert!(resolve_regions(&[region("chr3")], &header).is_err());
+    }
+
+    #[test]
+    fn contig_names_with_colons() {
+        let header = header(&[("HLA-A*01:01:01:01", 3_000), ("HLA-A*01:01:01", 3_000)]);
+        // the whole text is a contig first, as in samtools.
+        assert_eq!(
+            resolve_regions(&[region("HLA-A*01:01:01:01")], &header).unwrap(),
+            [(0, 0, 3000)]
+        );
+        assert_eq!(
+            resolve_regions(&[region("HLA-A*01:01:01:01:101-200")], &header).unwrap(),
+            [(0, 100, 200)]
+        );
+        assert!(resolve_regions(&[region("HLA-B*07:02:xx")], &header).is_err());
+    }
+
+    #[test]
+    fn chunks_own_each_record_once() {
+        let chunks = to_chunks(&[(0, 0, 250), (0, 400, 500), (1, 0, 100)], Some(100));
+        let own: Vec<(u32, i64, i64, i64)> = chunks
+            .iter()
+            .map(|x| (x.tid, x.start, x.end, x.own_start))
+            .collect();
+        assert_eq!(
+            own,
+            [
+                (0, 0, 100, i64::MIN),
+                (0, 100, 200, 100),
+                (0, 200, 250, 200),
+                (0, 400, 500, 250),
+                (1, 0, 100, i64::MIN),
+            ]
+        );
+    }
+}
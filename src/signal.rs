@@ -0,0 +1,411 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    aligments_event::AlignmentEvent, contig_naming::ContigNames, split_read_event::SplitReadEvent,
+    te::TeHit,
+};
+
+/// # Information of the read that a signal comes from
+#[derive(Debug, Clone)]
+pub struct ReadInfo {
+    pub qname: String,
+    /// 1 = forward, -1 = reverse
+    pub strand: i32,
+    pub flags: u16,
+    pub mapq: u8,
+    /// tid and 0-based position of the record in the BAM
+    pub tid: i32,
+    pub pos: i64,
+    /// `RG` tag of the record
+    pub read_group: Option<String>,
+    /// `SM` of the read group in the header
+    pub sample: Option<String>,
+}
+
+/// # Type of a signal
+///
+/// Fieldless counterpart of [`SvSignal`], used to filter signals and as the
+/// type column of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SignalType {
+    Split,
+    CigarDel,
+    CigarIns,
+    ClipInsOneSegment,
+    ClipInsTwoSegment,
+    Clip,
+    Discordant,
+}
+
+impl SignalType {
+    pub const ALL: [SignalType; 7] = [
+        SignalType::Split,
+        SignalType::CigarDel,
+        SignalType::CigarIns,
+        SignalType::ClipInsOneSegment,
+        SignalType::ClipInsTwoSegment,
+        SignalType::Clip,
+        SignalType::Discordant,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignalType::Split => "split",
+            SignalType::CigarDel => "cigar-del",
+            SignalType::CigarIns => "cigar-ins",
+            SignalType::ClipInsOneSegment => "clip-ins-one-segment",
+            SignalType::ClipInsTwoSegment => "clip-ins-two-segment",
+            SignalType::Clip => "clip",
+            SignalType::Discordant => "discordant",
+        }
+    }
+}
+
+impl fmt::Display for SignalType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SignalType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SignalType::ALL
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| format!("Unknown signal type: {}", s))
+    }
+}
+
+/// # SV type suggested by two adjacent segments of a split read
+///
+/// For segments `a` then `b` in read order:
+///
+/// - different contigs: translocation
+/// - strand switch: inversion
+/// - same strand, `b` after `a` in the direction of the read: deletion
+/// - same strand, `b` goes backwards on the reference: tandem duplication
+///
+/// Also used for discordant pairs, see [`SvSignal::Discordant`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SplitType {
+    Del,
+    Dup,
+    Inv,
+    Tra,
+}
+
+impl SplitType {
+    pub const ALL: [SplitType; 4] = [
+        SplitType::Del,
+        SplitType::Dup,
+        SplitType::Inv,
+        SplitType::Tra,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SplitType::Del => "DEL",
+            SplitType::Dup => "DUP",
+            SplitType::Inv => "INV",
+            SplitType::Tra => "TRA",
+        }
+    }
+}
+
+impl fmt::Display for SplitType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SplitType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SplitType::ALL
+            .into_iter()
+            .find(|x| x.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown split type: {}", s))
+    }
+}
+
+/// # End of the alignment that is clipped, in reference orientation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ClipSide {
+    /// Clip before the first aligned base
+    Left,
+    /// Clip after the last aligned base
+    Right,
+}
+
+impl ClipSide {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClipSide::Left => "left",
+            ClipSide::Right => "right",
+        }
+    }
+}
+
+impl fmt::Display for ClipSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// # SV signal extracted from one read
+///
+/// `seq` of the insertions is the inserted sequence on the forward strand of
+/// the reference, only captured with `ExtractorOptions::ins_seq` and when the
+/// bases are in the record(not hard-clipped). `te` is the best match of `seq`
+/// in the TE library given by `ExtractorOptions::te_library`.
+#[derive(Debug, Clone)]
+pub enum SvSignal {
+    /// Two adjacent segments of a split read, `left` is always the one with
+    /// the smaller reference position. `segments` is the number of adjacent
+    /// pairs in the read. `orientation` is the breakend orientation of `left`
+    /// and `right`: `+` when the junction is at the end of the segment, `-`
+    /// when it is at the start, e.g. `+-` for a deletion.
+    Split {
+        read: ReadInfo,
+        left: SplitReadEvent,
+        right: SplitReadEvent,
+        segments: usize,
+        sv_type: SplitType,
+        orientation: [char; 2],
+    },
+    /// Deletion derived from CIGAR, adjacent deletions may have been merged.
+    /// `len` is the reference length between the two regions.
+    CigarDel {
+        read: ReadInfo,
+        event: AlignmentEvent,
+        len: i64,
+    },
+    /// Insertion derived from CIGAR, the length is also encoded in the right region.
+    CigarIns {
+        read: ReadInfo,
+        event: AlignmentEvent,
+        len: i64,
+        seq: Option<String>,
+        te: Option<TeHit>,
+    },
+    /// Large insertion at the end of one segment. `clip_len` is the clip of
+    /// that segment and `segments` the number of alignments of the read.
+    ClipInsOneSegment {
+        read: ReadInfo,
+        event: AlignmentEvent,
+        clip_len: u32,
+        segments: usize,
+        seq: Option<String>,
+        te: Option<TeHit>,
+    },
+    /// Large insertion between two overlapping segments of the same read.
+    /// `clip_len` holds the clip of both segments in read order.
+    ClipInsTwoSegment {
+        read: ReadInfo,
+        event: AlignmentEvent,
+        clip_len: [u32; 2],
+        seq: Option<String>,
+        te: Option<TeHit>,
+    },
+    /// Soft clip of a record without `SA`, the clipped bases did not align.
+    /// `start` and `end` are the 0-based aligned interval, the breakpoint is
+    /// `start` for a left clip and `end` for a right clip. `len` is the length
+    /// of the clip.
+    Clip {
+        read: ReadInfo,
+        chrom: String,
+        start: i64,
+        end: i64,
+        side: ClipSide,
+        len: u32,
+    },
+    /// Discordant pair of short reads, reported from one mate only. `left` is
+    /// the mate with the smaller reference position, `orientation` the strand
+    /// of `left` and `right`(`+-` for a forward-reverse pair). `distance` is
+    /// the outer distance of the pair, `None` for mates on different contigs.
+    ///
+    /// - different contigs: translocation
+    /// - same strand(`++`, `--`): inversion
+    /// - reverse-forward(`-+`): tandem duplication
+    /// - forward-reverse(`+-`) further apart than the maximal insert size: deletion
+    Discordant {
+        read: ReadInfo,
+        left: SplitReadEvent,
+        right: SplitReadEvent,
+        sv_type: SplitType,
+        orientation: [char; 2],
+        distance: Option<i64>,
+    },
+}
+
+impl SvSignal {
+    pub fn signal_type(&self) -> SignalType {
+        match self {
+            SvSignal::Split { .. } => SignalType::Split,
+            SvSignal::CigarDel { .. } => SignalType::CigarDel,
+            SvSignal::CigarIns { .. } => SignalType::CigarIns,
+            SvSignal::ClipInsOneSegment { .. } => SignalType::ClipInsOneSegment,
+            SvSignal::ClipInsTwoSegment { .. } => SignalType::ClipInsTwoSegment,
+            SvSignal::Clip { .. } => SignalType::Clip,
+            SvSignal::Discordant { .. } => SignalType::Discordant,
+        }
+    }
+
+    /// SV type of a split read or discordant pair, `None` for the other signals.
+    pub fn split_type(&self) -> Option<SplitType> {
+        match self {
+            SvSignal::Split { sv_type, .. } | SvSignal::Discordant { sv_type, .. } => {
+                Some(*sv_type)
+            }
+            SvSignal::CigarDel { .. }
+            | SvSignal::CigarIns { .. }
+            | SvSignal::ClipInsOneSegment { .. }
+            | SvSignal::ClipInsTwoSegment { .. }
+            | SvSignal::Clip { .. } => None,
+        }
+    }
+
+    pub fn read(&self) -> &ReadInfo {
+        match self {
+            SvSignal::Split { read, .. }
+            | SvSignal::CigarDel { read, .. }
+            | SvSignal::CigarIns { read, .. }
+            | SvSignal::ClipInsOneSegment { read, .. }
+            | SvSignal::ClipInsTwoSegment { read, .. }
+            | SvSignal::Clip { read, .. }
+            | SvSignal::Discordant { read, .. } => read,
+        }
+    }
+
+    /// The inserted sequence of an insertion signal, if captured.
+    pub fn ins_seq(&self) -> Option<&str> {
+        match self {
+            SvSignal::CigarIns { seq, .. }
+            | SvSignal::ClipInsOneSegment { seq, .. }
+            | SvSignal::ClipInsTwoSegment { seq, .. } => seq.as_deref(),
+            SvSignal::Split { .. }
+            | SvSignal::CigarDel { .. }
+            | SvSignal::Clip { .. }
+            | SvSignal::Discordant { .. } => None,
+        }
+    }
+
+    /// The TE family of the inserted sequence, if classified.
+    pub fn ins_te(&self) -> Option<&TeHit> {
+        match self {
+            SvSignal::CigarIns { te, .. }
+            | SvSignal::ClipInsOneSegment { te, .. }
+            | SvSignal::ClipInsTwoSegment { te, .. } => te.as_ref(),
+            SvSignal::Split { .. }
+            | SvSignal::CigarDel { .. }
+            | SvSignal::Clip { .. }
+            | SvSignal::Discordant { .. } => None,
+        }
+    }
+
+    /// Rename the contigs of the signal, see [`ContigNames`].
+    pub fn rename_contigs(&mut self, names: &ContigNames) {
+        let rename = |x: &mut String| {
+            let renamed = names.rename(x);
+            if renamed != x.as_str() {
+                *x = renamed.into_owned();
+            }
+        };
+        match self {
+            SvSignal::Split { left, right, .. } | SvSignal::Discordant { left, right, .. } => {
+                rename(&mut left.chrom);
+                rename(&mut right.chrom);
+            }
+            SvSignal::CigarDel { event, .. }
+            | SvSignal::CigarIns { event, .. }
+            | SvSignal::ClipInsOneSegment { event, .. }
+            | SvSignal::ClipInsTwoSegment { event, .. } => {
+                rename(&mut event.lchrom);
+                rename(&mut event.rchrom);
+            }
+            SvSignal::Clip { chrom, .. } => rename(chrom),
+        }
+    }
+
+    /// # Breakpoints of the signal
+    ///
+    /// Contig and 0-based position of each junction: the junction ends of the
+    /// two segments of a split read or discordant pair given by the
+    /// orientation, the two sides of a CIGAR deletion, the insertion point of
+    /// an insertion and the clipped end of a clip.
+    pub fn breakpoints(&self) -> Vec<(&str, i64)> {
+        let junction = |x: &SplitReadEvent, o: char| if o == '+' { x.end } else { x.start };
+        match self {
+            SvSignal::Split {
+                left,
+                right,
+                orientation,
+                ..
+            }
+            | SvSignal::Discordant {
+                left,
+                right,
+                orientation,
+                ..
+            } => vec![
+                (left.chrom.as_str(), junction(left, orientation[0])),
+                (right.chrom.as_str(), junction(right, orientation[1])),
+            ],
+            SvSignal::CigarDel { event, .. } => vec![
+                (event.lchrom.as_str(), event.lend),
+                (event.rchrom.as_str(), event.rstart),
+            ],
+            SvSignal::CigarIns { event, .. }
+            | SvSignal::ClipInsOneSegment { event, .. }
+            | SvSignal::ClipInsTwoSegment { event, .. } => {
+                vec![(event.lchrom.as_str(), event.lend)]
+            }
+            SvSignal::Clip {
+                chrom,
+                start,
+                end,
+                side,
+                ..
+            } => match side {
+                ClipSide::Left => vec![(chrom.as_str(), *start)],
+                ClipSide::Right => vec![(chrom.as_str(), *end)],
+            },
+        }
+    }
+
+    /// Contig, 0-based start and end of every interval written for the
+    /// signal: both segments of a split read or discordant pair, both regions
+    /// of an alignment event and the aligned interval of a clip.
+    pub fn intervals(&self) -> Vec<(&str, i64, i64)> {
+        match self {
+            SvSignal::Split { left, right, .. } | SvSignal::Discordant { left, right, .. } => vec![
+                (left.chrom.as_str(), left.start, left.end),
+                (right.chrom.as_str(), right.start, right.end),
+            ],
+            SvSignal::CigarDel { event, .. }
+            | SvSignal::CigarIns { event, .. }
+            | SvSignal::ClipInsOneSegment { event, .. }
+            | SvSignal::ClipInsTwoSegment { event, .. } => vec![
+                (event.lchrom.as_str(), event.lstart, event.lend),
+                (event.rchrom.as_str(), event.rstart, event.rend),
+            ],
+            SvSignal::Clip {
+                chrom, start, end, ..
+            } => vec![(chrom.as_str(), *start, *end)],
+        }
+    }
+
+    /// The alignment event of the signal, `None` for split reads, clips and discordant pairs.
+    pub fn event(&self) -> Option<&AlignmentEvent> {
+        match self {
+            SvSignal::Split { .. } | SvSignal::Clip { .. } | SvSignal::Discordant { .. } => None,
+            SvSignal::CigarDel { event, .. }
+            | SvSignal::CigarIns { event, .. }
+            | SvSignal::ClipInsOneSegment { event, .. }
+            | SvSignal::ClipInsTwoSegment { event, .. } => Some(event),
+        }
+    }
+}
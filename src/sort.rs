@@ -0,0 +1,295 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Used to give unique names to the runs of all the sorters of the process.
+static NEXT_SORTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximal number of runs merged at once, each one is an open file.
+const MAX_FAN_IN: usize = 64;
+
+/// # One output line and the interval it is sorted by
+///
+/// `rank` is the position of the contig in the output.
+#[derive(Debug, Clone)]
+pub struct SortRecord {
+    pub rank: usize,
+    pub beg: i64,
+    pub end: i64,
+    pub line: String,
+}
+
+impl SortRecord {
+    fn key(&self) -> (usize, i64, i64) {
+        (self.rank, self.beg, self.end)
+    }
+
+    /// Approximate memory used by the record in the buffer.
+    fn mem_size(&self) -> usize {
+        mem::size_of::<SortRecord>() + self.line.capacity()
+    }
+}
+
+/// # External merge sort of the output records
+///
+/// The records are buffered until `max_mem` bytes are used, the buffer is
+/// then sorted and spilled to a run file in `tmp_dir`. `finish` merges the
+/// runs(k-way) with the records left in memory. With more than 64 runs,
+/// groups of 64 consecutive runs are first merged into intermediate runs, so
+/// no more than 64 files are open at once. The sort is stable: records with
+/// the same contig, start and end keep the order they were pushed in.
+pub struct ExternalSorter {
+    max_mem: usize,
+    tmp_dir: PathBuf,
+    id: usize,
+    used: usize,
+    buffer: Vec<SortRecord>,
+    runs: Vec<PathBuf>,
+    /// number of run files created, for their names
+    created: usize,
+}
+
+impl ExternalSorter {
+    pub fn new(max_mem: usize, tmp_dir: &Path) -> ExternalSorter {
+        ExternalSorter {
+            max_mem,
+            tmp_dir: tmp_dir.to_path_buf(),
+            id: NEXT_SORTER.fetch_add(1, Ordering::SeqCst),
+            used: 0,
+            buffer: vec![],
+            runs: vec![],
+            created: 0,
+        }
+    }
+
+    pub fn push(&mut self, x: SortRecord) -> io::Result<()> {
+        self.used += x.mem_size();
+        self.buffer.push(x);
+        if self.used >= self.max_mem {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Create a new run file, it is removed with the sorter.
+    fn create_run(&mut self) -> io::Result<(PathBuf, BufWriter<File>)> {
+        let path = self.tmp_dir.join(format!(
+            "excord-lr.{}.{}.{}.run",
+            process::id(),
+            self.id,
+            self.created
+        ));
+        self.created += 1;
+        let f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        Ok((path, BufWriter::new(f)))
+    }
+
+    /// Sort the buffer and write it to a new run file.
+    fn spill(&mut self) -> io::Result<()> {
+        let (path, mut f) = self.create_run()?;
+        self.runs.push(path);
+        let mut buffer = mem::take(&mut self.buffer);
+        buffer.sort_by_key(SortRecord::key);
+        for x in buffer {
+            write_record(&mut f, &x)?;
+        }
+        f.flush()?;
+        self.used = 0;
+        Ok(())
+    }
+
+    /// Merge groups of consecutive runs until at most `MAX_FAN_IN` are left.
+    fn reduce_runs(&mut self) -> io::Result<()> {
+        while self.runs.len() > MAX_FAN_IN {
+            let runs = mem::take(&mut self.runs);
+            for group in runs.chunks(MAX_FAN_IN) {
+                if let [run] = group {
+                    self.runs.push(run.clone());
+                    continue;
+                }
+                let (path, mut f) = self.create_run()?;
+                self.runs.push(path);
+                merge(group, |x| write_record(&mut f, &x))?;
+                f.flush()?;
+                for x in group {
+                    let _ = fs::remove_file(x);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hand all the records to `f` in sorted order.
+    pub fn finish<F>(mut self, f: F) -> io::Result<()>
+    where
+        F: FnMut(SortRecord) -> io::Result<()>,
+    {
+        if self.runs.is_empty() {
+            let mut buffer = mem::take(&mut self.buffer);
+            buffer.sort_by_key(SortRecord::key);
+            return buffer.into_iter().try_for_each(f);
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        self.reduce_runs()?;
+        merge(&self.runs, f)
+    }
+}
+
+fn write_record(f: &mut impl Write, x: &SortRecord) -> io::Result<()> {
+    writeln!(f, "{}\t{}\t{}\t{}", x.rank, x.beg, x.end, x.line)
+}
+
+/// K-way merge of sorted runs, ties are broken by the index of the run.
+fn merge<F>(runs: &[PathBuf], mut f: F) -> io::Result<()>
+where
+    F: FnMut(SortRecord) -> io::Result<()>,
+{
+    let mut readers = runs
+        .iter()
+        .map(|x| RunReader::open(x))
+        .collect::<io::Result<Vec<RunReader>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (i, r) in readers.iter_mut().enumerate() {
+        if let Some(x) = r.next_record()? {
+            heap.push(Reverse((x.key(), i, x.line)));
+        }
+    }
+    while let Some(Reverse(((rank, beg, end), i, line))) = heap.pop() {
+        f(SortRecord {
+            rank,
+            beg,
+            end,
+            line,
+        })?;
+        if let Some(x) = readers[i].next_record()? {
+            heap.push(Reverse((x.key(), i, x.line)));
+        }
+    }
+    Ok(())
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        for x in &self.runs {
+            let _ = fs::remove_file(x);
+        }
+    }
+}
+
+struct RunReader {
+    path: PathBuf,
+    inner: BufReader<File>,
+    line: String,
+}
+
+impl RunReader {
+    fn open(path: &Path) -> io::Result<RunReader> {
+        Ok(RunReader {
+            path: path.to_path_buf(),
+            inner: BufReader::new(File::open(path)?),
+            line: String::new(),
+        })
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<SortRecord>> {
+        self.line.clear();
+        if self.inner.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+        let mut cols = self.line.trim_end_matches('\n').splitn(4, '\t');
+        let mut field = || {
+            cols.next()
+                .ok_or_else(|| io::Error::other(format!("Corrupted run {}", self.path.display())))
+        };
+        let rank = field()?.parse::<usize>().map_err(io::Error::other)?;
+        let beg = field()?.parse::<i64>().map_err(io::Error::other)?;
+        let end = field()?.parse::<i64>().map_err(io::Error::other)?;
+        let line = field()?.to_string();
+        Ok(Some(SortRecord {
+            rank,
+            beg,
+            end,
+            line,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(rank: usize, beg: i64, end: i64, line: &str) -> SortRecord {
+        SortRecord {
+            rank,
+            beg,
+            end,
+            line: line.to_string(),
+        }
+    }
+
+    fn sorted(max_mem: usize, records: Vec<SortRecord>) -> Vec<String> {
+        let mut sorter = ExternalSorter::new(max_mem, &std::env::temp_dir());
+        for x in records {
+            sorter.push(x).unwrap();
+        }
+        let mut out = vec![];
+        sorter
+            .finish(|x| {
+                out.push(x.line);
+                Ok(())
+            })
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn sorts_by_contig_start_end_in_memory() {
+        let out = sorted(
+            usize::MAX,
+            vec![
+                record(1, 5, 10, "c"),
+                record(0, 20, 30, "b"),
+                record(0, 20, 25, "a"),
+                record(1, 0, 10, "d"),
+            ],
+        );
+        assert_eq!(out, ["a", "b", "d", "c"]);
+    }
+
+    #[test]
+    fn ties_keep_the_push_order_across_runs() {
+        // every record is spilled to its own run.
+        let records = (0..10)
+            .map(|i| record(0, 100, 200, &i.to_string()))
+            .collect();
+        let out = sorted(1, records);
+        let expected: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn merges_more_runs_than_the_fan_in() {
+        let n = MAX_FAN_IN * 3 + 5;
+        let records = (0..n)
+            .map(|i| record(0, ((i * 7919) % n) as i64, 0, &i.to_string()))
+            .collect();
+        let out = sorted(1, records);
+        let mut expected: Vec<usize> = (0..n).collect();
+        expected.sort_by_key(|i| (i * 7919) % n);
+        let expected: Vec<String> = expected.iter().map(|i| i.to_string()).collect();
+        assert_eq!(out, expected);
+    }
+}
@@ -1,5 +1,8 @@
 use std::collections::HashMap;
-#[derive(Debug)]
+
+use crate::utils::query_coords;
+
+#[derive(Debug, Clone)]
 pub struct SplitReadEvent {
     pub chrom: String,
     pub start: i64,
@@ -8,6 +11,10 @@ pub struct SplitReadEvent {
     pub strand: i32, // true = forward
     pub mapq: u8,
     pub raw_cigar: String,
+    /// 0-based start of the aligned part in the original read orientation
+    pub query_start: i64,
+    /// end(exclusive) of the aligned part in the original read orientation
+    pub query_end: i64,
 }
 
 impl SplitReadEvent {
@@ -25,22 +32,24 @@ impl SplitReadEvent {
             + (*cigar_map.get(&'M').unwrap()) as i64
             + (*cigar_map.get(&'=').unwrap()) as i64
             + (*cigar_map.get(&'X').unwrap()) as i64
-            + -1i64;
-        let chrom_clean: String;
-        if chrom.starts_with(&"chr".to_string()) {
-            chrom_clean = chrom.strip_prefix(&"chr".to_string()).unwrap().to_string();
-        } else {
-            chrom_clean = chrom.to_string();
-        }
+            - 1i64;
+        let (query_start, query_end) = query_coords(cigar_string, *strand);
 
         SplitReadEvent {
-            chrom: String::from(chrom_clean),
+            chrom: chrom.to_string(),
             start: *start,
             end: end + 1,
-            cigar_map: cigar_map,
+            cigar_map,
             strand: *strand,
             mapq: *mapq,
             raw_cigar: cigar_string.to_string(),
+            query_start,
+            query_end,
         }
     }
+
+    /// The longer one of the total soft-clip and the total hard-clip.
+    pub fn clip_len(&self) -> u32 {
+        (*self.cigar_map.get(&'S').unwrap()).max(*self.cigar_map.get(&'H').unwrap())
+    }
 }
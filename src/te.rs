@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// # Best match of an inserted sequence in the TE library
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeHit {
+    /// Name of the consensus, e.g. `AluYa5`, `L1HS` or `SVA_F`
+    pub family: String,
+    /// Identity estimated from the shared k-mers, see [`TeLibrary::classify`]
+    pub identity: f64,
+}
+
+impl fmt::Display for TeHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{:.3}", self.family, self.identity)
+    }
+}
+
+/// # Consensus sequences of mobile elements indexed by k-mer
+///
+/// Every canonical k-mer(the smaller of the k-mer and its reverse complement)
+/// of each consensus is indexed, so insertions are matched on both strands.
+#[derive(Debug)]
+pub struct TeLibrary {
+    k: usize,
+    min_identity: f64,
+    families: Vec<String>,
+    index: HashMap<u64, Vec<u32>>,
+}
+
+impl TeLibrary {
+    /// Read a FASTA of consensus sequences, e.g. from Dfam or RepBase.
+    ///
+    /// The family is the name of the record up to the first space or `#`
+    /// (`AluY#SINE/Alu` -> `AluY`). `k` must be between 1 and 31.
+    pub fn from_fasta(path: &Path, k: usize, min_identity: f64) -> Result<TeLibrary, String> {
+        if !(1..=31).contains(&k) {
+            return Err(format!("Invalid k-mer size: {}, must be 1-31", k));
+        }
+        let f = File::open(path).map_err(|e| e.to_string())?;
+        let mut records: Vec<(String, Vec<u8>)> = vec![];
+        for line in BufReader::new(f).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim_end();
+            if let Some(name) = line.strip_prefix('>') {
+                let name = name.split([' ', '\t', '#']).next().unwrap_or_default();
+                records.push((name.to_string(), vec![]));
+            } else if let Some((_, seq)) = records.last_mut() {
+                seq.extend_from_slice(line.as_bytes());
+            } else if !line.is_empty() {
+                return Err("not a FASTA file".to_string());
+            }
+        }
+        if records.is_empty() {
+            return Err("no sequence in the TE library".to_string());
+        }
+
+        let mut library = TeLibrary {
+            k,
+            min_identity,
+            families: vec![],
+            index: HashMap::new(),
+        };
+        for (i, (name, seq)) in records.into_iter().enumerate() {
+            library.families.push(name);
+            for kmer in canonical_kmers(&seq, k)
+                .into_iter()
+                .collect::<HashSet<u64>>()
+            {
+                library.index.entry(kmer).or_default().push(i as u32);
+            }
+        }
+        Ok(library)
+    }
+
+    /// Number of consensus sequences.
+    pub fn len(&self) -> usize {
+        self.families.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+
+    /// # Family of an inserted sequence
+    ///
+    /// The consensus sharing the most distinct k-mers with `seq` is the best
+    /// match. Its containment `c`(shared k-mers / k-mers of `seq`) is turned
+    /// into an identity as `c^(1/k)`, the same estimate as Mash. `None` if the
+    /// identity is below the minimum.
+    pub fn classify(&self, seq: &str) -> Option<TeHit> {
+        let kmers: HashSet<u64> = canonical_kmers(seq.as_bytes(), self.k)
+            .into_iter()
+            .collect();
+        if kmers.is_empty() {
+            return None;
+        }
+        let mut shared = vec![0u32; self.families.len()];
+        for kmer in &kmers {
+            for i in self.index.get(kmer).into_iter().flatten() {
+                shared[*i as usize] += 1;
+            }
+        }
+        // the first family wins a tie.
+        let (best, n) =
+            shared
+                .iter()
+                .enumerate()
+                .fold((0, 0), |acc, (i, n)| if *n > acc.1 { (i, *n) } else { acc });
+        if n == 0 {
+            return None;
+        }
+        let identity = (n as f64 / kmers.len() as f64).powf(1.0 / self.k as f64);
+        (identity >= self.min_identity).then(|| TeHit {
+            family: self.families[best].clone(),
+            identity,
+        })
+    }
+}
+
+/// 2-bit encoded canonical k-mers of a sequence, k-mers with other bases than `ACGT` are skipped.
+fn canonical_kmers(seq: &[u8], k: usize) -> Vec<u64> {
+    let mask = (1u64 << (2 * k)) - 1;
+    let shift = 2 * (k - 1);
+    let mut fwd = 0u64;
+    let mut rev = 0u64;
+    let mut len = 0usize;
+    let mut kmers = vec![];
+    for x in seq {
+        let code = match x.to_ascii_uppercase() {
+            b'A' => 0u64,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => {
+                len = 0;
+                continue;
+            }
+        };
+        fwd = ((fwd << 2) | code) & mask;
+        rev = (rev >> 2) | ((3 - code) << shift);
+        len += 1;
+        if len >= k {
+            kmers.push(fwd.min(rev));
+        }
+    }
+    kmers
+}
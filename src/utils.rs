@@ -1,6 +1,10 @@
-use rust_htslib::bam::Record;
-
-use crate::{aligments_event::AlignmentEvent, split_read_event::SplitReadEvent};
+use crate::{
+    aligments_event::AlignmentEvent,
+    signal::{ClipSide, ReadInfo, SignalType, SplitType},
+    split_read_event::SplitReadEvent,
+    te::TeHit,
+};
+use rust_htslib::{bam::HeaderView, htslib};
 use std::{
     cmp::Ordering,
     collections::HashMap,
@@ -8,6 +12,27 @@ use std::{
     path::{Path, PathBuf},
 };
 
+/// # Contig name used in the output
+///
+/// The leading "chr" is removed, e.g. "chr1" -> "1", "chr2L" -> "2L". `None`
+/// for "chr" itself, which would be left without a name, a name without
+/// "chr" is kept.
+pub fn clean_chrom(chrom: &str) -> Option<&str> {
+    match chrom.strip_prefix("chr") {
+        Some("") => None,
+        Some(rest) => Some(rest),
+        None => Some(chrom),
+    }
+}
+
+/// # Length of a contig of the header
+///
+/// `HeaderView::target_len` is read from the 32-bit array of the header, the
+/// length of a contig longer than 2^32 bp is only kept by htslib as 64-bit.
+pub fn contig_len(header: &HeaderView, tid: u32) -> i64 {
+    unsafe { htslib::sam_hdr_tid2len(header.inner_ptr(), tid as i32) }
+}
+
 /// # get the position of the first match base.
 pub fn find_first_match_pos(cigar_str: &str) -> i64 {
     let mut p = 0i64;
@@ -38,14 +63,76 @@ pub fn find_first_match_pos(cigar_str: &str) -> i64 {
         }
     }
     // dbg!(cigar_str, p);
-    return p;
+    p
+}
+
+/// # Query interval of an alignment in the original read orientation
+///
+/// The CIGAR is given in reference orientation, the clips(`S` and `H`) before
+/// the first aligned base are the read offset of a forward alignment. For a
+/// reverse alignment the read offset is counted from the other end, i.e. the
+/// clips after the last aligned base.
+///
+/// ```text
+/// 20S30M100S, forward -> [20, 50)
+/// 20S30M100S, reverse -> [100, 130)
+/// 20H30M100H, forward -> [20, 50)
+/// ```
+pub fn query_coords(cigar_str: &str, strand: i32) -> (i64, i64) {
+    let mut leading_clip = 0i64;
+    let mut trailing_clip = 0i64;
+    let mut aligned = 0i64;
+    let mut n_str = String::new();
+    for c in cigar_str.chars() {
+        if c.is_ascii_digit() {
+            n_str.push(c);
+            continue;
+        }
+        let n = n_str.parse::<i64>().unwrap_or(0);
+        n_str.clear();
+        match c {
+            'S' | 'H' if aligned == 0 => leading_clip += n,
+            'S' | 'H' => trailing_clip += n,
+            // S I X = M consume query
+            'M' | 'I' | '=' | 'X' => aligned += n,
+            _ => {}
+        }
+    }
+    let offset = if strand < 0 {
+        trailing_clip
+    } else {
+        leading_clip
+    };
+    (offset, offset + aligned)
+}
+
+/// # Clips at both ends of an alignment
+///
+/// Total length of `S` and `H` before the first and after the last aligned
+/// base, in the reference orientation of the CIGAR.
+pub fn clip_sides(cigar_str: &str) -> (i64, i64) {
+    (query_coords(cigar_str, 1).0, query_coords(cigar_str, -1).0)
+}
+
+/// Reverse complement of a sequence, other bases than `ACGT` become `N`.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|x| match x.to_ascii_uppercase() {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
 }
 
 /// # Compare two Alignment
 ///
 /// From brentp:
 ///
-/// ```
+/// ```text
 ///
 /// output splitters. Splitters are ordered by their offset into the read.
 /// given, cigars of:
@@ -55,26 +142,48 @@ pub fn find_first_match_pos(cigar_str: &str) -> i64 {
 /// we would order them as they are listed. We would output bedpe intervals
 /// for A-B, and B-C
 /// ```
+///
+/// The offsets are the strand-aware query starts, see [`query_coords`].
 pub fn splitter_order_cmp(a: &SplitReadEvent, b: &SplitReadEvent) -> Ordering {
-    let a_first_match_pos = find_first_match_pos(&a.raw_cigar);
-    let b_first_match_pos = find_first_match_pos(&b.raw_cigar);
-    // dbg!(a,a_first_match_pos,b,b_first_match_pos,a_first_match_pos.cmp(&b_first_match_pos));
-    // dbg!(a_first_match_pos,b_first_match_pos,a_first_match_pos.cmp(&b_first_match_pos));
-    if a_first_match_pos < b_first_match_pos {
-        Ordering::Less
+    a.query_start.cmp(&b.query_start)
+}
+
+/// Overlap(bp) of the two segments of a deletion still called a deletion.
+/// Aligners often give the microhomology at the junction to both segments.
+pub const SPLIT_DEL_OVERLAP: i64 = 20;
+
+/// # Classify two adjacent segments of a split read
+///
+/// `a` and `b` are given in read order. Return the SV type and the breakend
+/// orientation of `a` and `b`: a forward segment leaves the junction by its end
+/// (`+`) and enters it by its start (`-`), the other way around for a reverse
+/// segment. `b` may overlap `a` by up to [`SPLIT_DEL_OVERLAP`] bp for a deletion.
+///
+/// ```text
+/// DEL  a(+) ---->        b(+) ---->      +-
+/// DUP  b(+) ---->        a(+) ---->      -+
+/// INV  a(+) ---->        b(-) <----      ++
+/// ```
+pub fn classify_split(a: &SplitReadEvent, b: &SplitReadEvent) -> (SplitType, [char; 2]) {
+    let leave = if a.strand < 0 { '-' } else { '+' };
+    let enter = if b.strand < 0 { '+' } else { '-' };
+    let sv_type = if a.chrom != b.chrom {
+        SplitType::Tra
+    } else if a.strand != b.strand {
+        SplitType::Inv
+    } else if (a.strand >= 0 && b.start + SPLIT_DEL_OVERLAP >= a.end)
+        || (a.strand < 0 && b.end - SPLIT_DEL_OVERLAP <= a.start)
+    {
+        SplitType::Del
     } else {
-        if a_first_match_pos > b_first_match_pos {
-            Ordering::Greater
-        } else {
-            Ordering::Equal
-        }
-    }
-    // a_first_match_pos.cmp(&b_first_match_pos)
+        SplitType::Dup
+    };
+    (sv_type, [leave, enter])
 }
 
 pub fn alignment_pos_cmp(a: &SplitReadEvent, b: &SplitReadEvent) -> Ordering {
     if a.chrom.cmp(&b.chrom) != Ordering::Equal {
-        a.chrom.as_bytes().cmp(&b.chrom.as_bytes())
+        a.chrom.as_bytes().cmp(b.chrom.as_bytes())
     } else {
         // if a.start.cmp(&b.start) != Ordering::Equal {
         //     a.start.cmp(&b.start)
@@ -116,26 +225,57 @@ pub fn parse_cigar(cigar_str: &str) -> HashMap<char, u32> {
     cigar_map
 }
 
-pub fn parse_supplementary_alignment(s: &str) -> SplitReadEvent {
+/// # Check a CIGAR string, e.g. from an SA tag
+///
+/// Every operation must be preceded by its length.
+pub fn is_valid_cigar(cigar_str: &str) -> bool {
+    let mut has_len = false;
+    for c in cigar_str.chars() {
+        if c.is_ascii_digit() {
+            has_len = true;
+        } else if "MIDNSHP=X".contains(c) && has_len {
+            has_len = false;
+        } else {
+            return false;
+        }
+    }
+    !cigar_str.is_empty() && !has_len
+}
+
+/// # Parse one alignment of an SA tag
+///
+/// `rname,pos,strand,CIGAR,mapQ,NM`, pos is 1-based.
+pub fn parse_supplementary_alignment(s: &str) -> Result<SplitReadEvent, String> {
+    let invalid = |field: &str| format!("Invalid {} in SA: {}", field, s);
     let sa_vec: Vec<&str> = s.split(',').collect();
+    if sa_vec.len() < 6 {
+        return Err(format!("Expected 6 fields in SA: {}", s));
+    }
     let chrom = sa_vec[0];
-    let pos = sa_vec[1].parse::<i64>().unwrap();
+    let pos = sa_vec[1]
+        .parse::<i64>()
+        .ok()
+        .filter(|x| *x > 0)
+        .ok_or_else(|| invalid("position"))?;
     let strand = match sa_vec[2] {
-        "+" => Ok(1),
-        "-" => Ok(-1),
-        _ => Err("No strand info"),
+        "+" => 1,
+        "-" => -1,
+        _ => return Err(invalid("strand")),
     };
+    if !is_valid_cigar(sa_vec[3]) {
+        return Err(invalid("CIGAR"));
+    }
     let cigar_str = parse_cigar(sa_vec[3]);
-    let mapq = sa_vec[4].parse::<u8>().unwrap();
-    let _nm = sa_vec[5].parse::<i64>().unwrap();
-    SplitReadEvent::new(
+    let mapq = sa_vec[4].parse::<u8>().map_err(|_| invalid("MapQ"))?;
+    let _nm = sa_vec[5].parse::<i64>().map_err(|_| invalid("NM"))?;
+    Ok(SplitReadEvent::new(
         chrom,
         &(pos - 1),
         cigar_str,
-        &strand.unwrap(),
+        &strand,
         &mapq,
-        &sa_vec[3],
-    )
+        sa_vec[3],
+    ))
 }
 
 pub fn absolute_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
@@ -149,6 +289,44 @@ pub fn absolute_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
     Ok(absolute_path)
 }
 
+/// # Path of one group of a split output
+///
+/// `name` is inserted before the extension, `.gz` is kept with the one before
+/// it. Characters that are unsafe in a file name become `_`.
+///
+/// ```text
+/// out.bed, NA12878     -> out.NA12878.bed
+/// out.vcf.gz, NA12878  -> out.NA12878.vcf.gz
+/// ```
+pub fn group_path(path: &Path, name: &str) -> PathBuf {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let (stem, ext) = split_extension(path);
+    path.with_file_name(format!("{}.{}{}", stem, name, ext))
+}
+
+/// File name of a path split before its extension, `.gz` is kept with the one before it.
+pub fn split_extension(path: &Path) -> (String, String) {
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext_len = match file_name.strip_suffix(".gz") {
+        Some(stem) => stem.rfind('.').map_or(3, |i| file_name.len() - i),
+        None => file_name.rfind('.').map_or(0, |i| file_name.len() - i),
+    };
+    let (stem, ext) = file_name.split_at(file_name.len() - ext_len);
+    (stem.to_string(), ext.to_string())
+}
+
 /// # calucate the overlap between the left interval and the right interval.
 ///
 /// max_over_pct represents the max percent of overlap threshold. Any bed
@@ -158,7 +336,7 @@ pub fn absolute_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
 pub fn overlap(a_start: &i64, a_end: &i64, b_start: &i64, b_end: &i64, max_over_pct: f64) -> bool {
     // dbg!(&a_start,&a_end,&b_start,&b_end);
     if a_end < b_start || a_start > b_end {
-        return false;
+        false
     } else {
         let min_len = (a_end - a_start).min(b_end - b_start);
         let ov: f64;
@@ -185,26 +363,21 @@ pub fn overlap(a_start: &i64, a_end: &i64, b_start: &i64, b_end: &i64, max_over_
             }
         }
 
-        if ov > max_over_pct {
-            return true;
-        } else {
-            return false;
-        }
+        ov > max_over_pct
     }
 }
 
 pub fn get_alignment_event_record(
     x: &AlignmentEvent,
     verbose: &bool,
-    record: &Record,
-    strand: &i32,
+    read: &ReadInfo,
     tag: &str,
+    seq: Option<&str>,
+    te: Option<&TeHit>,
 ) -> String {
-    let rrr: String;
     if *verbose {
-        rrr = format!(
-            // "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tstrand:{}\tflag:{}\n",
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tstrand:{}\tflag:{}\n",
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tstrand:{}\tflag:{}{}{}\n",
             x.lchrom,
             x.lstart,
             x.lend,
@@ -214,15 +387,15 @@ pub fn get_alignment_event_record(
             x.rend,
             x.rstrand,
             x.events_num,
-            // "excord-lr-alignment-event",
-            // std::str::from_utf8(record.qname()).unwrap()
             tag,
-            std::str::from_utf8(record.qname()).unwrap(),
-            strand,
-            record.flags()
-        );
+            read.qname,
+            read.strand,
+            read.flags,
+            seq.map(|x| format!("\tseq:{}", x)).unwrap_or_default(),
+            te.map(|x| format!("\tte:{}", x)).unwrap_or_default()
+        )
     } else {
-        rrr = format!(
+        format!(
             "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
             x.lchrom,
             x.lstart,
@@ -233,24 +406,95 @@ pub fn get_alignment_event_record(
             x.rend,
             x.rstrand,
             x.events_num
-        );
+        )
+    }
+}
+
+/// # BEDPE-like record of a clip
+///
+/// The aligned part of the read is the left region for a right clip and the
+/// right region for a left clip, the other region is the breakpoint.
+pub fn get_clip_record(
+    chrom: &str,
+    start: i64,
+    end: i64,
+    side: ClipSide,
+    len: u32,
+    verbose: &bool,
+    read: &ReadInfo,
+) -> String {
+    let (l, r) = match side {
+        ClipSide::Left => ((start, start), (start, end)),
+        ClipSide::Right => ((start, end), (end, end)),
+    };
+    let record = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t1",
+        chrom, l.0, l.1, read.strand, chrom, r.0, r.1, read.strand
+    );
+    if *verbose {
+        format!(
+            "{}\t{}\t{}\tstrand:{}\tflag:{}\tclip:{},{}\n",
+            record,
+            SignalType::Clip,
+            read.qname,
+            read.strand,
+            read.flags,
+            side,
+            len
+        )
+    } else {
+        format!("{}\n", record)
+    }
+}
+
+/// # BEDPE-like record of a discordant pair
+///
+/// Laid out like a split-read pair, with one pair per line.
+pub fn get_discordant_record(
+    a: &SplitReadEvent,
+    b: &SplitReadEvent,
+    verbose: &bool,
+    read: &ReadInfo,
+    sv_type: &SplitType,
+    orientation: &[char; 2],
+    distance: Option<i64>,
+) -> String {
+    let record = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t1",
+        a.chrom, a.start, a.end, a.strand, b.chrom, b.start, b.end, b.strand
+    );
+    if *verbose {
+        format!(
+            "{}\t{}\t{}\tstrand:{}\tflag:{}\tsv:{}\tori:{}{}{}\n",
+            record,
+            SignalType::Discordant,
+            read.qname,
+            read.strand,
+            read.flags,
+            sv_type,
+            orientation[0],
+            orientation[1],
+            distance
+                .map(|x| format!("\tdist:{}", x))
+                .unwrap_or_default()
+        )
+    } else {
+        format!("{}\n", record)
     }
-    return rrr;
 }
 
 pub fn get_alignment_split_record(
     a: &SplitReadEvent,
     b: &SplitReadEvent,
     verbose: &bool,
-    record: &Record,
-    strand: &i32,
-    align_vec_len: &usize,
-    tag: &str,
+    read: &ReadInfo,
+    segments: &usize,
+    sv_type: &SplitType,
+    orientation: &[char; 2],
 ) -> String {
-    let bed_line: String;
     if *verbose {
-        bed_line = format!(
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tstrand:{}\tflag:{}\n",
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tstrand:{}\tflag:{}\tquery:{}-{},{}-{}\tsv:{}\tori:{}{}\n",
             a.chrom,
             a.start,
             a.end,
@@ -259,31 +503,112 @@ pub fn get_alignment_split_record(
             b.start,
             b.end,
             b.strand,
-            *align_vec_len - 1,
-            tag,
-            std::str::from_utf8(record.qname()).unwrap(),
-            strand,
-            record.flags()
-        );
+            segments,
+            SignalType::Split,
+            read.qname,
+            read.strand,
+            read.flags,
+            a.query_start,
+            a.query_end,
+            b.query_start,
+            b.query_end,
+            sv_type,
+            orientation[0],
+            orientation[1]
+        )
     } else {
-        bed_line = format!(
+        format!(
             "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
-            a.chrom,
-            a.start,
-            a.end,
-            a.strand,
-            b.chrom,
-            b.start,
-            b.end,
-            b.strand,
-            *align_vec_len - 1
-        );
+            a.chrom, a.start, a.end, a.strand, b.chrom, b.start, b.end, b.strand, segments
+        )
     }
-    return bed_line;
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(sa: &str) -> SplitReadEvent {
+        parse_supplementary_alignment(sa).unwrap()
+    }
+
+    #[test]
+    fn query_coords_follow_the_read_orientation() {
+        assert_eq!(query_coords("20S30M100S", 1), (20, 50));
+        assert_eq!(query_coords("20S30M100S", -1), (100, 130));
+        assert_eq!(query_coords("20H30M100H", 1), (20, 50));
+        assert_eq!(query_coords("10S20M5I10M3D5M", 1), (10, 50));
+        assert_eq!(clip_sides("20S30M100H"), (20, 100));
+    }
+
+    #[test]
+    fn strips_a_leading_chr() {
+        assert_eq!(clean_chrom("chr1"), Some("1"));
+        assert_eq!(clean_chrom("chrUn_KI270302v1"), Some("Un_KI270302v1"));
+        assert_eq!(clean_chrom("chr2L"), Some("2L"));
+        assert_eq!(clean_chrom("chromosome1"), Some("omosome1"));
+        assert_eq!(clean_chrom("1"), Some("1"));
+        assert_eq!(clean_chrom("Chr1"), Some("Chr1"));
+        assert_eq!(clean_chrom("chr"), None);
+    }
+
+    #[test]
+    fn parses_sa_alignments() {
+        let x = segment("chr2,1001,-,100S50M,60,3");
+        assert_eq!(
+            (x.chrom.as_str(), x.start, x.end, x.strand),
+            ("chr2", 1000, 1050, -1)
+        );
+        assert_eq!((x.query_start, x.query_end), (0, 50));
+        assert!(parse_supplementary_alignment("chr2,0,+,50M,60,0").is_err());
+        assert!(parse_supplementary_alignment("chr2,1001,*,50M,60,0").is_err());
+        assert!(parse_supplementary_alignment("chr2,1001,+,50Q,60,0").is_err());
+        assert!(parse_supplementary_alignment("chr2,1001,+,50M,60").is_err());
+    }
+
+    #[test]
+    fn classifies_split_pairs() {
+        let a = segment("1,1001,+,100M100S,60,0");
+        let del = segment("1,5001,+,100S100M,60,0");
+        assert_eq!(classify_split(&a, &del), (SplitType::Del, ['+', '-']));
+        let dup = segment("1,501,+,100S100M,60,0");
+        assert_eq!(classify_split(&a, &dup), (SplitType::Dup, ['+', '-']));
+        let inv = segment("1,5001,-,100M100S,60,0");
+        assert_eq!(classify_split(&a, &inv), (SplitType::Inv, ['+', '+']));
+        let tra = segment("2,5001,+,100S100M,60,0");
+        assert_eq!(classify_split(&a, &tra), (SplitType::Tra, ['+', '-']));
+
+        // reverse segments: the read goes from the larger position to the smaller.
+        let a = segment("1,5001,-,100S100M,60,0");
+        let del = segment("1,1001,-,100M100S,60,0");
+        assert_eq!(classify_split(&a, &del), (SplitType::Del, ['-', '+']));
+        assert_eq!(classify_split(&del, &a).0, SplitType::Dup);
+    }
 
-// fn merge_two_alignment_event(a:&AlignmentEvent,b:&AlignmentEvent)->Vec<AlignmentEvent> {
-    
+    #[test]
+    fn microhomology_overlap_is_still_a_deletion() {
+        // a ends at 1100, b starts up to SPLIT_DEL_OVERLAP bp before.
+        let a = segment("1,1001,+,100M100S,60,0");
+        let b = |start: i64| segment(&format!("1,{},+,100S100M,60,0", start + 1));
+        assert_eq!(classify_split(&a, &b(1100)).0, SplitType::Del);
+        assert_eq!(
+            classify_split(&a, &b(1100 - SPLIT_DEL_OVERLAP)).0,
+            SplitType::Del
+        );
+        assert_eq!(
+            classify_split(&a, &b(1099 - SPLIT_DEL_OVERLAP)).0,
+            SplitType::Dup
+        );
 
-// }
\ No newline at end of file
+        let a = segment("1,2001,-,100S100M,60,0");
+        let b = |end: i64| segment(&format!("1,{},-,100M100S,60,0", end - 99));
+        assert_eq!(
+            classify_split(&a, &b(2000 + SPLIT_DEL_OVERLAP)).0,
+            SplitType::Del
+        );
+        assert_eq!(
+            classify_split(&a, &b(2001 + SPLIT_DEL_OVERLAP)).0,
+            SplitType::Dup
+        );
+    }
+}
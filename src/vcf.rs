@@ -0,0 +1,443 @@
+use rust_htslib::bam::HeaderView;
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use crate::{
+    aligments_event::AlignmentEvent,
+    contig_naming::ContigNames,
+    signal::{ClipSide, ReadInfo, SvSignal},
+    split_read_event::SplitReadEvent,
+    utils::contig_len,
+    writer::{check_range, Annotation, SignalWriter},
+};
+
+/// Largest position of a record, `POS` and `END` are 32-bit integers in VCF.
+const MAX_POS: i64 = i32::MAX as i64;
+
+const VCF_META: &str = "##ALT=<ID=DEL,Description=\"Deletion\">
+##ALT=<ID=INS,Description=\"Insertion\">
+##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">
+##INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of the variant\">
+##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Difference in length between REF and ALT alleles\">
+##INFO=<ID=MATEID,Number=.,Type=String,Description=\"ID of the mate breakend\">
+##INFO=<ID=SIGNAL,Number=1,Type=String,Description=\"Type of the excord-lr signal\">
+##INFO=<ID=SPLITTYPE,Number=1,Type=String,Description=\"SV type suggested by a split-read or discordant pair(DEL,DUP,INV,TRA)\">
+##INFO=<ID=STRANDS,Number=1,Type=String,Description=\"Breakend orientation of a split-read or discordant pair\">
+##INFO=<ID=INSERTSIZE,Number=1,Type=Integer,Description=\"Outer distance of a discordant pair\">
+##INFO=<ID=SEGMENTS,Number=1,Type=Integer,Description=\"Number of adjacent segment pairs of the split read\">
+##INFO=<ID=CLIPLEN,Number=.,Type=Integer,Description=\"Length of the clips supporting a large insertion\">
+##INFO=<ID=CLIPSIDE,Number=1,Type=String,Description=\"Clipped end of the alignment(left,right)\">
+##INFO=<ID=INSSEQ,Number=1,Type=String,Description=\"Inserted sequence captured from the read\">
+##INFO=<ID=TEFAMILY,Number=1,Type=String,Description=\"TE family of the inserted sequence\">
+##INFO=<ID=TEIDENTITY,Number=1,Type=Float,Description=\"Identity of the inserted sequence to the TE consensus, estimated from shared k-mers\">
+##INFO=<ID=SAMPLE,Number=1,Type=String,Description=\"Sample of the read group of the supporting read\">
+##INFO=<ID=REFREADS,Number=1,Type=Integer,Description=\"Reads spanning the breakpoints without any signal\">
+##INFO=<ID=ALTREADS,Number=1,Type=Integer,Description=\"Reads with a signal at the breakpoints\">
+##INFO=<ID=VAF,Number=1,Type=Float,Description=\"Fraction of alternate reads, ALTREADS/(REFREADS+ALTREADS)\">
+##INFO=<ID=NORMALREADS,Number=1,Type=Integer,Description=\"Reads of the normal sample with a matching signal\">
+##INFO=<ID=READ,Number=1,Type=String,Description=\"Name of the supporting read, percent-encoded\">
+##INFO=<ID=MAPQ,Number=1,Type=Integer,Description=\"Mapping quality of the supporting record\">
+##INFO=<ID=FLAG,Number=1,Type=Integer,Description=\"SAM flag of the supporting record\">
+";
+
+/// # Per-read breakend signals as VCF
+///
+/// Split-read and discordant pairs are written as two mate `BND` records
+/// (`ID`/`MATEID` `bnd<N>_1` and `bnd<N>_2`) with the bracket notation of VCF 4.2, CIGAR deletions and insertions as symbolic
+/// `<DEL>`/`<INS>` alleles and clips as single breakends(`N.` or `.N`).
+/// No reference is used, so `REF` is always `N`.
+///
+/// With [`VcfWriter::with_sample`] the sample of the read is added as `SAMPLE`
+/// to INFO when known. The [`Annotation`] of [`SignalWriter::write_annotated`]
+/// is added as `REFREADS`, `ALTREADS`, `VAF` and `NORMALREADS`.
+pub struct VcfWriter<W: Write> {
+    inner: W,
+    sample: bool,
+    /// number of the last signal written
+    next_id: u64,
+}
+
+impl<W: Write> VcfWriter<W> {
+    /// Create the writer and write the header, the contigs are taken from the
+    /// BAM header and renamed with `names`.
+    pub fn new(mut inner: W, header: &HeaderView, names: &ContigNames) -> io::Result<VcfWriter<W>> {
+        writeln!(inner, "##fileformat=VCFv4.2")?;
+        writeln!(inner, "##source=excord-lr")?;
+        for tid in 0..header.target_count() {
+            let name = String::from_utf8_lossy(header.tid2name(tid)).into_owned();
+            writeln!(
+                inner,
+                "##contig=<ID={},length={}>",
+                names.rename(&name),
+                contig_len(header, tid)
+            )?;
+        }
+        inner.write_all(VCF_META.as_bytes())?;
+        writeln!(inner, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+        Ok(VcfWriter {
+            inner,
+            sample: false,
+            next_id: 0,
+        })
+    }
+
+    /// Add the sample to INFO.
+    pub fn with_sample(mut self, sample: bool) -> VcfWriter<W> {
+        self.sample = sample;
+        self
+    }
+
+    /// Give the underlying writer back, call `finish` first.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// 1-based position of a breakend, `+` is the end of the segment and `-` its start.
+fn breakend_pos(x: &SplitReadEvent, ori: char) -> i64 {
+    if ori == '+' {
+        x.end
+    } else {
+        x.start + 1
+    }
+}
+
+/// ALT of a breakend joined to `chrom:pos` with orientation `ori`(this breakend, mate).
+///
+/// ```text
+/// +-  N[p[
+/// ++  N]p]
+/// -+  ]p]N
+/// --  [p[N
+/// ```
+pub fn bnd_alt(ori: [char; 2], chrom: &str, pos: i64) -> String {
+    match ori {
+        ['+', '-'] => format!("N[{}:{}[", chrom, pos),
+        ['+', '+'] => format!("N]{}:{}]", chrom, pos),
+        ['-', '+'] => format!("]{}:{}]N", chrom, pos),
+        _ => format!("[{}:{}[N", chrom, pos),
+    }
+}
+
+/// `BND` pair of the breakends of `left` and `right`, `bnd<id>_1` at `left`
+/// and `bnd<id>_2` at `right`, each pointing to the other with `MATEID`.
+fn bnd_record(
+    left: &SplitReadEvent,
+    right: &SplitReadEvent,
+    orientation: [char; 2],
+    id: u64,
+    info: String,
+) -> String {
+    let left_pos = breakend_pos(left, orientation[0]);
+    let right_pos = breakend_pos(right, orientation[1]);
+    format!(
+        "{}\t{}\tbnd{}_1\tN\t{}\t.\tPASS\tSVTYPE=BND;MATEID=bnd{}_2;{}\n\
+         {}\t{}\tbnd{}_2\tN\t{}\t.\tPASS\tSVTYPE=BND;MATEID=bnd{}_1;{}\n",
+        left.chrom,
+        left_pos,
+        id,
+        bnd_alt(orientation, &right.chrom, right_pos),
+        id,
+        info,
+        right.chrom,
+        right_pos,
+        id,
+        bnd_alt([orientation[1], orientation[0]], &left.chrom, left_pos),
+        id,
+        info
+    )
+}
+
+/// Percent-encode the characters with a meaning in INFO(`;`, `=`, `,`, `%`)
+/// and whitespace, as in VCF 4.3.
+fn encode_info(x: &str) -> Cow<'_, str> {
+    if !x
+        .chars()
+        .any(|c| matches!(c, ';' | '=' | ',' | '%') || c.is_whitespace())
+    {
+        return Cow::Borrowed(x);
+    }
+    let mut out = String::with_capacity(x.len() + 8);
+    for c in x.chars() {
+        if matches!(c, ';' | '=' | ',' | '%') || c.is_whitespace() {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    Cow::Owned(out)
+}
+
+fn read_info(read: &ReadInfo) -> String {
+    format!(
+        "READ={};MAPQ={};FLAG={}",
+        encode_info(&read.qname),
+        read.mapq,
+        read.flags
+    )
+}
+
+/// Format one signal as VCF lines, `id` numbers the `BND` pair of a split
+/// read or discordant pair.
+pub fn get_vcf_record(x: &SvSignal, id: u64) -> String {
+    let signal = x.signal_type();
+    let symbolic = |event: &AlignmentEvent, alt: &str, info: String| {
+        format!(
+            "{}\t{}\t.\tN\t<{}>\t.\tPASS\tSVTYPE={};{};SIGNAL={};{}{}{}\n",
+            event.lchrom,
+            event.lend,
+            alt,
+            alt,
+            info,
+            signal,
+            read_info(x.read()),
+            x.ins_seq()
+                .map(|x| format!(";INSSEQ={}", x))
+                .unwrap_or_default(),
+            x.ins_te()
+                .map(|x| format!(";TEFAMILY={};TEIDENTITY={:.3}", x.family, x.identity))
+                .unwrap_or_default()
+        )
+    };
+    match x {
+        SvSignal::Split {
+            read,
+            left,
+            right,
+            segments,
+            sv_type,
+            orientation,
+        } => bnd_record(
+            left,
+            right,
+            *orientation,
+            id,
+            format!(
+                "SIGNAL={};SPLITTYPE={};STRANDS={}{};SEGMENTS={};{}",
+                signal,
+                sv_type,
+                orientation[0],
+                orientation[1],
+                segments,
+                read_info(read)
+            ),
+        ),
+        SvSignal::Discordant {
+            read,
+            left,
+            right,
+            sv_type,
+            orientation,
+            distance,
+        } => bnd_record(
+            left,
+            right,
+            *orientation,
+            id,
+            format!(
+                "SIGNAL={};SPLITTYPE={};STRANDS={}{};{}{}",
+                signal,
+                sv_type,
+                orientation[0],
+                orientation[1],
+                distance
+                    .map(|x| format!("INSERTSIZE={};", x))
+                    .unwrap_or_default(),
+                read_info(read)
+            ),
+        ),
+        SvSignal::CigarDel { event, len, .. } => {
+            symbolic(event, "DEL", format!("END={};SVLEN=-{}", event.rstart, len))
+        }
+        SvSignal::CigarIns { event, len, .. } => {
+            symbolic(event, "INS", format!("END={};SVLEN={}", event.lend, len))
+        }
+        SvSignal::ClipInsOneSegment {
+            event, clip_len, ..
+        } => symbolic(
+            event,
+            "INS",
+            format!("END={};CLIPLEN={}", event.lend, clip_len),
+        ),
+        SvSignal::ClipInsTwoSegment {
+            event, clip_len, ..
+        } => symbolic(
+            event,
+            "INS",
+            format!("END={};CLIPLEN={},{}", event.lend, clip_len[0], clip_len[1]),
+        ),
+        SvSignal::Clip {
+            read,
+            chrom,
+            start,
+            end,
+            side,
+            len,
+        } => {
+            let (pos, alt) = match side {
+                ClipSide::Left => (start + 1, ".N"),
+                ClipSide::Right => (*end, "N."),
+            };
+            format!(
+                "{}\t{}\t.\tN\t{}\t.\tPASS\tSVTYPE=BND;SIGNAL={};CLIPSIDE={};CLIPLEN={};{}\n",
+                chrom,
+                pos,
+                alt,
+                signal,
+                side,
+                len,
+                read_info(read)
+            )
+        }
+    }
+}
+
+impl<W: Write> VcfWriter<W> {
+    fn write_line(&mut self, x: &SvSignal, annotation: &Annotation) -> io::Result<()> {
+        check_range(x, MAX_POS, "VCF")?;
+        self.next_id += 1;
+        let record = get_vcf_record(x, self.next_id);
+        let mut info = String::new();
+        if let Some(sample) = x.read().sample.as_deref().filter(|_| self.sample) {
+            info.push_str(&format!(";SAMPLE={}", encode_info(sample)));
+        }
+        if let Some(counts) = &annotation.counts {
+            info.push_str(&format!(
+                ";REFREADS={};ALTREADS={};VAF={}",
+                counts.ref_reads,
+                counts.alt_reads,
+                counts
+                    .vaf()
+                    .map(|x| format!("{:.3}", x))
+                    .unwrap_or_else(|| ".".to_string())
+            ));
+        }
+        if let Some(normal) = annotation.normal {
+            info.push_str(&format!(";NORMALREADS={}", normal));
+        }
+        // both lines of a BND pair get the same INFO.
+        for line in record.lines() {
+            writeln!(self.inner, "{}{}", line, info)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> SignalWriter for VcfWriter<W> {
+    fn write_signal(&mut self, x: &SvSignal) -> io::Result<()> {
+        self.write_line(x, &Annotation::default())
+    }
+
+    fn write_annotated(&mut self, x: &SvSignal, annotation: &Annotation) -> io::Result<()> {
+        self.write_line(x, annotation)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{alignment_pos_cmp, classify_split, parse_supplementary_alignment};
+
+    fn read() -> ReadInfo {
+        ReadInfo {
+            qname: "r;1".to_string(),
+            strand: 1,
+            flags: 0,
+            mapq: 60,
+            tid: 0,
+            pos: 1000,
+            read_group: None,
+            sample: None,
+        }
+    }
+
+    /// Split pair of two SA alignments given in read order, `left` first in the reference.
+    fn split(a: &str, b: &str) -> SvSignal {
+        let a = parse_supplementary_alignment(a).unwrap();
+        let b = parse_supplementary_alignment(b).unwrap();
+        let (sv_type, [oa, ob]) = classify_split(&a, &b);
+        let (left, right, orientation) = if alignment_pos_cmp(&a, &b).is_le() {
+            (a, b, [oa, ob])
+        } else {
+            (b, a, [ob, oa])
+        };
+        SvSignal::Split {
+            read: read(),
+            left,
+            right,
+            segments: 1,
+            sv_type,
+            orientation,
+        }
+    }
+
+    fn columns(line: &str) -> Vec<&str> {
+        line.split('\t').collect()
+    }
+
+    #[test]
+    fn bnd_alts_of_the_four_orientations() {
+        assert_eq!(bnd_alt(['+', '-'], "2", 70001), "N[2:70001[");
+        assert_eq!(bnd_alt(['+', '+'], "2", 70001), "N]2:70001]");
+        assert_eq!(bnd_alt(['-', '+'], "2", 70001), "]2:70001]N");
+        assert_eq!(bnd_alt(['-', '-'], "2", 70001), "[2:70001[N");
+    }
+
+    #[test]
+    fn split_pairs_are_mate_breakends() {
+        let x = split("1,1001,+,100M100S,60,0", "1,5001,+,100S100M,60,0");
+        let record = get_vcf_record(&x, 7);
+        let lines: Vec<Vec<&str>> = record.lines().map(columns).collect();
+        assert_eq!(lines.len(), 2);
+        // the end of the left segment is joined to the start of the right one.
+        assert_eq!(&lines[0][..5], ["1", "1100", "bnd7_1", "N", "N[1:5001["]);
+        assert_eq!(&lines[1][..5], ["1", "5001", "bnd7_2", "N", "]1:1100]N"]);
+        assert!(lines[0][7].starts_with("SVTYPE=BND;MATEID=bnd7_2;SIGNAL=split;SPLITTYPE=DEL;"));
+        assert!(lines[1][7].starts_with("SVTYPE=BND;MATEID=bnd7_1;"));
+        assert!(lines[0][7].ends_with("READ=r%3B1;MAPQ=60;FLAG=0"));
+    }
+
+    #[test]
+    fn inversions_and_duplications_point_to_the_right_ends() {
+        let x = split("1,1001,+,100M100S,60,0", "1,5001,-,100M100S,60,0");
+        let record = get_vcf_record(&x, 1);
+        let alts: Vec<&str> = record.lines().map(|x| columns(x)[4]).collect();
+        assert_eq!(alts, ["N]1:5100]", "N]1:1100]"]);
+        let x = split("1,5001,+,100M100S,60,0", "1,1001,+,100S100M,60,0");
+        let record = get_vcf_record(&x, 1);
+        let lines: Vec<Vec<&str>> = record.lines().map(columns).collect();
+        assert_eq!((lines[0][1], lines[0][4]), ("1001", "]1:5100]N"));
+        assert_eq!((lines[1][1], lines[1][4]), ("5100", "N[1:1001["));
+    }
+
+    #[test]
+    fn clips_are_single_breakends() {
+        let clip = |side| SvSignal::Clip {
+            read: read(),
+            chrom: "1".to_string(),
+            start: 1000,
+            end: 1500,
+            side,
+            len: 300,
+        };
+        let left = get_vcf_record(&clip(ClipSide::Left), 1);
+        assert_eq!(&columns(&left)[1..5], ["1001", ".", "N", ".N"]);
+        let right = get_vcf_record(&clip(ClipSide::Right), 1);
+        assert_eq!(&columns(&right)[1..5], ["1500", ".", "N", "N."]);
+    }
+
+    #[test]
+    fn info_values_are_percent_encoded() {
+        assert_eq!(encode_info("read/1"), "read/1");
+        assert_eq!(encode_info("a;b=c,d%e f"), "a%3Bb%3Dc%2Cd%25e%20f");
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::{self, Write};
+
+use crate::{
+    bed_record::BedColumns, error::ExcordError, ref_support::AlleleCounts, signal::SvSignal,
+    utils::*,
+};
+
+/// # Output of the extracted signals
+///
+/// Every output format implements this trait, `finish` must be called once
+/// all signals have been written.
+pub trait SignalWriter {
+    fn write_signal(&mut self, x: &SvSignal) -> io::Result<()>;
+
+    /// Write a signal with the columns computed after the extraction, formats
+    /// without a place for them ignore them.
+    fn write_annotated(&mut self, x: &SvSignal, annotation: &Annotation) -> io::Result<()> {
+        let _ = annotation;
+        self.write_signal(x)
+    }
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// # Columns of a signal computed after the extraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Annotation {
+    /// Reads of the reference and the alternate allele, see `--ref-support`
+    pub counts: Option<AlleleCounts>,
+    /// Number of normal reads with a matching signal, see `--normal-bam`
+    pub normal: Option<usize>,
+}
+
+/// # Range check of the written coordinates
+///
+/// The 0-based intervals of `x` must be within `0..=max`, the largest
+/// coordinate of the `format`. A signal out of range is an error, with the
+/// [`ExcordError::Record`] as its source, instead of a line the readers of
+/// the format reject.
+pub fn check_range(x: &SvSignal, max: i64, format: &str) -> io::Result<()> {
+    for (chrom, start, end) in x.intervals() {
+        if start < 0 || end > max {
+            let pos = if start < 0 { start } else { end };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                ExcordError::Record {
+                    qname: x.read().qname.clone(),
+                    contig: chrom.to_string(),
+                    pos,
+                    msg: format!("the position does not fit in {}", format),
+                },
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Error when the 0-based interval `start..end` of `chrom` is not within
+/// `0..=max` of the `format`, for the outputs without a read.
+pub fn check_interval(chrom: &str, start: i64, end: i64, max: i64, format: &str) -> io::Result<()> {
+    if start < 0 || end > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}-{} does not fit in {}", chrom, start, end, format),
+        ));
+    }
+    Ok(())
+}
+
+/// # Tab-separated BEDPE-like output read by STIX
+///
+/// In verbose mode the type of the signal, the read name, the strand and the
+/// flag of the read are appended, and the inserted sequence when captured and
+/// its TE family with the identity(`te:AluYa5,0.962`) when classified. Clips
+/// end with their side and length(`clip:right,350`), discordant pairs with the
+/// SV type, the orientation and the outer distance(`sv:DEL\tori:+-\tdist:5200`).
+///
+/// With [`BedWriter::with_sample`] the sample of the read is inserted as the
+/// 10th column, before the verbose columns, `.` when unknown. The
+/// [`Annotation`] of [`SignalWriter::write_annotated`] comes next: the `ref`,
+/// `alt` and `vaf` columns, then the `normal` column, each declared with
+/// [`BedWriter::with_annotation`]. With any of them the output starts with
+/// the header of [`BedColumns`].
+pub struct BedWriter<W: Write> {
+    inner: W,
+    verbose: bool,
+    columns: BedColumns,
+    header_written: bool,
+}
+
+impl<W: Write> BedWriter<W> {
+    pub fn new(inner: W, verbose: bool) -> BedWriter<W> {
+        BedWriter {
+            inner,
+            verbose,
+            columns: BedColumns::default(),
+            header_written: false,
+        }
+    }
+
+    /// Add the sample column.
+    pub fn with_sample(mut self, sample: bool) -> BedWriter<W> {
+        self.columns.sample = sample;
+        self
+    }
+
+    /// Add the `ref`/`alt`/`vaf` columns and the `normal` column.
+    pub fn with_annotation(mut self, counts: bool, normal: bool) -> BedWriter<W> {
+        self.columns.counts = counts;
+        self.columns.normal = normal;
+        self
+    }
+
+    /// Give the underlying writer back, call `finish` first.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Format one signal as a BEDPE-like line.
+pub fn get_bed_record(x: &SvSignal, verbose: &bool) -> String {
+    let tag = x.signal_type().as_str();
+    match x {
+        SvSignal::Split {
+            read,
+            left,
+            right,
+            segments,
+            sv_type,
+            orientation,
+        } => get_alignment_split_record(left, right, verbose, read, segments, sv_type, orientation),
+        SvSignal::CigarDel { read, event, .. }
+        | SvSignal::CigarIns { read, event, .. }
+        | SvSignal::ClipInsOneSegment { read, event, .. }
+        | SvSignal::ClipInsTwoSegment { read, event, .. } => {
+            get_alignment_event_record(event, verbose, read, tag, x.ins_seq(), x.ins_te())
+        }
+        SvSignal::Clip {
+            read,
+            chrom,
+            start,
+            end,
+            side,
+            len,
+        } => get_clip_record(chrom, *start, *end, *side, *len, verbose, read),
+        SvSignal::Discordant {
+            read,
+            left,
+            right,
+            sv_type,
+            orientation,
+            distance,
+        } => get_discordant_record(left, right, verbose, read, sv_type, orientation, *distance),
+    }
+}
+
+impl<W: Write> BedWriter<W> {
+    fn write_line(&mut self, x: &SvSignal, annotation: &Annotation) -> io::Result<()> {
+        if !self.header_written && !self.columns.is_empty() {
+            writeln!(self.inner, "{}", self.columns.header())?;
+        }
+        self.header_written = true;
+        check_range(x, i64::MAX, "BED")?;
+        let mut line = get_bed_record(x, &self.verbose);
+        let mut columns = String::new();
+        if self.columns.sample {
+            columns.push('\t');
+            columns.push_str(x.read().sample.as_deref().unwrap_or("."));
+        }
+        if let Some(counts) = &annotation.counts {
+            columns.push_str(&format!("\t{}", counts));
+        }
+        if let Some(normal) = annotation.normal {
+            columns.push_str(&format!("\t{}", normal));
+        }
+        if !columns.is_empty() {
+            // after the 9 BEDPE-like columns.
+            let at = line
+                .match_indices('\t')
+                .nth(8)
+                .map_or(line.len() - 1, |(i, _)| i);
+            line.insert_str(at, &columns);
+        }
+        self.inner.write_all(line.as_bytes())
+    }
+}
+
+impl<W: Write> SignalWriter for BedWriter<W> {
+    fn write_signal(&mut self, x: &SvSignal) -> io::Result<()> {
+        self.write_line(x, &Annotation::default())
+    }
+
+    fn write_annotated(&mut self, x: &SvSignal, annotation: &Annotation) -> io::Result<()> {
+        self.write_line(x, annotation)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// # FASTA of the captured insertion sequences
+///
+/// One entry per insertion signal with a sequence, the name is
+/// `<read>:<contig>:<pos>` with the 1-based position of the base before the
+/// insertion(`POS` of the VCF output), followed by the signal type, length and
+/// the TE family when classified.
+pub struct InsFastaWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> InsFastaWriter<W> {
+    pub fn new(inner: W) -> InsFastaWriter<W> {
+        InsFastaWriter { inner }
+    }
+}
+
+impl<W: Write> SignalWriter for InsFastaWriter<W> {
+    fn write_signal(&mut self, x: &SvSignal) -> io::Result<()> {
+        let (Some(seq), Some(event)) = (x.ins_seq(), x.event()) else {
+            return Ok(());
+        };
+        writeln!(
+            self.inner,
+            ">{}:{}:{} {} len={}{}\n{}",
+            x.read().qname,
+            event.lchrom,
+            event.lend,
+            x.signal_type(),
+            seq.len(),
+            x.ins_te()
+                .map(|x| format!(" te={}", x.family))
+                .unwrap_or_default(),
+            seq
+        )
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::{ClipSide, ReadInfo};
+
+    fn clip(start: i64, end: i64) -> SvSignal {
+        SvSignal::Clip {
+            read: ReadInfo {
+                qname: "read".to_string(),
+                strand: 1,
+                flags: 0,
+                mapq: 60,
+                tid: 0,
+                pos: start,
+                read_group: None,
+                sample: None,
+            },
+            chrom: "1".to_string(),
+            start,
+            end,
+            side: ClipSide::Right,
+            len: 500,
+        }
+    }
+
+    #[test]
+    fn coordinates_out_of_the_format_are_errors() {
+        let big = clip(1 << 32, (1 << 32) + 1000);
+        assert!(check_range(&big, i64::MAX, "BED").is_ok());
+        let e = check_range(&big, i32::MAX as i64, "VCF").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.get_ref().unwrap().is::<ExcordError>());
+        assert!(check_range(&clip(-1, 100), i64::MAX, "BED").is_err());
+        assert!(check_interval("1", 0, 100, i64::MAX, "BED").is_ok());
+        assert!(check_interval("1", -5, 100, i64::MAX, "BED").is_err());
+    }
+
+    #[test]
+    fn bed_writer_stops_at_a_negative_position() {
+        let mut w = BedWriter::new(vec![], false);
+        w.write_signal(&clip(100, 600)).unwrap();
+        assert!(w.write_signal(&clip(-1, 600)).is_err());
+        assert_eq!(
+            String::from_utf8(w.into_inner()).unwrap().lines().count(),
+            1
+        );
+    }
+}